@@ -0,0 +1,80 @@
+use crate::settings::HttpClient;
+use failure::Error;
+use reqwest::Certificate;
+use reqwest::Client;
+use reqwest::Identity;
+use reqwest::Proxy;
+use std::fs;
+use std::time::Duration;
+
+/// Builds the one HTTP client shared by all outbound calls so TLS, proxy and
+/// pooling behave the same for every upstream.
+pub fn client_from_settings(settings: &HttpClient) -> Result<Client, Error> {
+    let mut builder = Client::builder()
+        .connect_timeout(Duration::from_millis(settings.connect_timeout_ms))
+        .pool_max_idle_per_host(settings.pool_max_idle_per_host)
+        .pool_idle_timeout(Duration::from_secs(settings.pool_idle_timeout_secs))
+        .user_agent(settings.user_agent.as_str());
+    if let Some(ca_bundle) = &settings.ca_bundle {
+        let pem = fs::read(ca_bundle)?;
+        let certificates = Certificate::from_pem_bundle(&pem)?;
+        if certificates.is_empty() {
+            return Err(failure::err_msg(format!(
+                "no certificates in {}",
+                ca_bundle
+            )));
+        }
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    if let Some(proxy) = &settings.proxy {
+        builder = builder.proxy(Proxy::all(proxy)?);
+    }
+    if let Some(identity) = &settings.identity {
+        let cert = fs::read(&identity.cert_file)?;
+        let key = fs::read(&identity.key_file)?;
+        builder = builder.identity(Identity::from_pkcs8_pem(&cert, &key)?);
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_settings_build() {
+        assert!(client_from_settings(&HttpClient::default()).is_ok());
+    }
+
+    #[test]
+    fn test_missing_ca_bundle_fails() {
+        let settings = HttpClient {
+            ca_bundle: Some(String::from("/does/not/exist.pem")),
+            ..Default::default()
+        };
+        assert!(client_from_settings(&settings).is_err());
+    }
+
+    #[test]
+    fn test_empty_ca_bundle_fails() {
+        let path = std::env::temp_dir().join("dino-park-empty-ca-bundle.pem");
+        fs::write(&path, "").unwrap();
+        let settings = HttpClient {
+            ca_bundle: Some(path.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        assert!(client_from_settings(&settings).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_proxy_fails() {
+        let settings = HttpClient {
+            proxy: Some(String::from("not a url")),
+            ..Default::default()
+        };
+        assert!(client_from_settings(&settings).is_err());
+    }
+}
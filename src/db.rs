@@ -0,0 +1,38 @@
+use crate::settings::Database;
+use deadpool_postgres::Config;
+use deadpool_postgres::Pool;
+use deadpool_postgres::PoolConfig;
+use deadpool_postgres::Runtime;
+use failure::Error;
+use native_tls::TlsConnector;
+use postgres_native_tls::MakeTlsConnector;
+
+/// Tables owned by the fence, created on startup.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS username_history (
+    username TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    current TEXT NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX IF NOT EXISTS username_history_user_id ON username_history (user_id);
+";
+
+/// TLS is used as requested by `sslmode` in the url.
+pub fn tls() -> Result<MakeTlsConnector, Error> {
+    Ok(MakeTlsConnector::new(TlsConnector::new()?))
+}
+
+/// Connects to the configured database, `None` if there is none.
+pub async fn pool_from_settings(settings: &Database) -> Result<Option<Pool>, Error> {
+    let url = match &settings.url {
+        Some(url) => url,
+        None => return Ok(None),
+    };
+    let mut config = Config::new();
+    config.url = Some(url.clone());
+    config.pool = Some(PoolConfig::new(settings.pool_size));
+    let pool = config.create_pool(Some(Runtime::Tokio1), tls()?)?;
+    pool.get().await?.batch_execute(SCHEMA).await?;
+    Ok(Some(pool))
+}
@@ -6,11 +6,14 @@
 //  at the same level as their item
 #![allow(non_local_definitions)]
 
+use crate::picture::error::PictureError;
 use actix_web::error::ResponseError;
+use actix_web::http::header::RETRY_AFTER;
 use actix_web::HttpResponse;
 use dino_park_trust::GroupsTrustError;
 use dino_park_trust::TrustError;
 use failure::Fail;
+use log::error;
 use log::warn;
 use serde_json::json;
 use serde_json::Value;
@@ -20,6 +23,20 @@ use std::fmt::Display;
 pub enum ApiError {
     #[fail(display = "Proxy error occured.")]
     ProxyError,
+    #[fail(display = "Not found.")]
+    NotFound,
+    #[fail(display = "Upstream rejected the request with {}.", _0)]
+    UpstreamClientError(u16),
+    /// Upstreams refusing our credentials are a problem of Fence, not the
+    /// client.
+    #[fail(display = "Upstream refused our credentials with {}.", _0)]
+    UpstreamAuthError(u16),
+    #[fail(display = "Upstream payload too large.")]
+    PayloadTooLarge,
+    #[fail(display = "Upstream {} is unavailable.", _0)]
+    UpstreamUnavailable(&'static str),
+    #[fail(display = "Too many requests, retry after {} seconds.", _0)]
+    RateLimited(u64),
     #[fail(display = "Unknown error occurred.")]
     Unknown,
     #[fail(display = "Bad API request.")]
@@ -28,6 +45,8 @@ pub enum ApiError {
     ScopeError(TrustError),
     #[fail(display = "Groups scope Error: {}", _0)]
     GroupsScopeError(GroupsTrustError),
+    #[fail(display = "{}", _0)]
+    PictureError(PictureError),
 }
 
 fn to_json_error(e: &impl Display) -> Value {
@@ -46,6 +65,12 @@ impl From<GroupsTrustError> for ApiError {
     }
 }
 
+impl From<PictureError> for ApiError {
+    fn from(e: PictureError) -> Self {
+        ApiError::PictureError(e)
+    }
+}
+
 impl From<failure::Error> for ApiError {
     fn from(e: failure::Error) -> Self {
         ApiError::GenericBadRequest(e)
@@ -59,8 +84,25 @@ impl ResponseError for ApiError {
                 warn!("{}", e);
                 HttpResponse::BadRequest().finish()
             }
+            Self::NotFound => HttpResponse::NotFound().finish(),
+            Self::UpstreamClientError(_) => HttpResponse::BadRequest().json(to_json_error(self)),
+            Self::UpstreamAuthError(_) => {
+                error!("{}", self);
+                HttpResponse::BadGateway().finish()
+            }
+            Self::PayloadTooLarge => HttpResponse::BadGateway().finish(),
+            Self::UpstreamUnavailable(_) => {
+                HttpResponse::ServiceUnavailable().json(to_json_error(self))
+            }
+            Self::RateLimited(retry_after) => HttpResponse::TooManyRequests()
+                .insert_header((RETRY_AFTER, retry_after.to_string()))
+                .json(to_json_error(self)),
             Self::ScopeError(ref e) => HttpResponse::Forbidden().json(to_json_error(e)),
             Self::GroupsScopeError(ref e) => HttpResponse::Forbidden().json(to_json_error(e)),
+            Self::PictureError(PictureError::TooLarge(_)) => {
+                HttpResponse::PayloadTooLarge().json(to_json_error(self))
+            }
+            Self::PictureError(ref e) => HttpResponse::BadRequest().json(to_json_error(e)),
             _ => HttpResponse::InternalServerError().finish(),
         }
     }
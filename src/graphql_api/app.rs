@@ -1,22 +1,125 @@
 use crate::error::ApiError;
-use crate::graphql_api::root::{Mutation, Query, Schema};
+use crate::graphql_api::audit::sink_from_settings;
+use crate::graphql_api::bus::ProfileBus;
+use crate::graphql_api::error::strip_internal_errors;
+use crate::graphql_api::error::QueryLimitError;
+use crate::graphql_api::limits::check_limits;
+use crate::graphql_api::loader::Loader;
+use crate::graphql_api::root::{Context, Mutation, Query, Schema, Subscription};
+use crate::graphql_api::username::ReservedNames;
 use crate::metrics::Metrics;
+use crate::orgchart::cache::OrgchartCache;
+use crate::outbound::Outbound;
+use crate::ratelimit::RateLimiter;
 use crate::settings::DinoParkServices;
+use crate::settings::GraphQl;
+use crate::usernames::history::UsernameHistory;
 use actix_web::dev::HttpServiceFactory;
+use actix_web::http::header::HeaderValue;
+use actix_web::http::header::SEC_WEBSOCKET_PROTOCOL;
+use actix_web::http::StatusCode;
 use actix_web::web;
+use actix_web::web::Bytes;
 use actix_web::web::Data;
+use actix_web::HttpMessage;
+use actix_web::HttpRequest;
 use actix_web::HttpResponse;
+use actix_ws::CloseCode;
+use actix_ws::CloseReason;
+use actix_ws::Message;
+use actix_ws::MessageStream;
+use actix_ws::Session;
 use cis_client::AsyncCisClientTrait;
 use dino_park_gate::scope::ScopeAndUser;
 use dino_park_guard::guard;
+use futures::future::ready;
+use futures::stream;
+use futures::SinkExt;
+use futures::StreamExt;
 use juniper::http::graphiql::graphiql_source;
-use juniper_actix::graphql_handler;
+use juniper::http::GraphQLBatchRequest;
+use juniper::http::GraphQLRequest;
+use juniper::DefaultScalarValue;
+use juniper_graphql_ws::ArcSchema;
+use juniper_graphql_ws::ClientMessage;
+use juniper_graphql_ws::Connection;
+use juniper_graphql_ws::ConnectionConfig;
+use juniper_graphql_ws::ServerMessage;
+use log::error;
 use log::info;
+use log::warn;
+use serde_json::json;
 use std::sync::Arc;
+use std::time::Duration;
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
 
 #[derive(Clone)]
 pub struct GraphQlState<T: AsyncCisClientTrait + Send + Sync + 'static> {
     schema: Arc<Schema<T>>,
+    settings: GraphQl,
+}
+
+#[derive(Deserialize)]
+struct QueryOnly {
+    query: String,
+}
+
+/// Just the queries of a (batch) request, to check them before execution.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Queries {
+    Single(QueryOnly),
+    Batch(Vec<QueryOnly>),
+}
+
+impl Queries {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            Queries::Single(q) => vec![q.query],
+            Queries::Batch(qs) => qs.into_iter().map(|q| q.query).collect(),
+        }
+    }
+}
+
+fn parse_request(
+    req: &HttpRequest,
+    body: &[u8],
+) -> Result<(Vec<String>, GraphQLBatchRequest), failure::Error> {
+    if req.content_type() == "application/graphql" {
+        let query = String::from_utf8(body.to_vec())?;
+        Ok((
+            vec![query.clone()],
+            GraphQLBatchRequest::Single(GraphQLRequest::new(query, None, None)),
+        ))
+    } else {
+        let queries: Queries = serde_json::from_slice(body)?;
+        Ok((queries.into_vec(), serde_json::from_slice(body)?))
+    }
+}
+
+/// Request level errors in the same shape as errors from execution.
+fn bad_request(message: &str, code: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({
+        "errors": [{ "message": message, "extensions": { "code": code } }]
+    }))
+}
+
+/// Checks the queries of a request against the limits, counting rejections.
+fn check_queries<Q: AsRef<str>>(
+    queries: &[Q],
+    limits: &GraphQl,
+    metrics: &Metrics,
+    scope_and_user: &ScopeAndUser,
+) -> Result<(), QueryLimitError> {
+    check_limits(queries, limits).map(|_| ()).inspect_err(|e| {
+        info!("rejected query for {}: {}", scope_and_user.user_id, e);
+        metrics
+            .counters
+            .graphql_rejected
+            .with_label_values(&[e.as_str()])
+            .inc();
+    })
 }
 
 #[guard(Staff)]
@@ -29,8 +132,8 @@ async fn graphiql() -> Result<HttpResponse, ApiError> {
 
 #[guard(Authenticated)]
 async fn graphql<T: AsyncCisClientTrait + Send + Sync>(
-    req: actix_web::HttpRequest,
-    payload: actix_web::web::Payload,
+    req: HttpRequest,
+    body: Bytes,
     state: Data<GraphQlState<T>>,
     scope_and_user: ScopeAndUser,
     metrics: Data<Metrics>,
@@ -39,32 +142,224 @@ async fn graphql<T: AsyncCisClientTrait + Send + Sync>(
         "graphql for {:?} → {:?}",
         &scope_and_user.user_id, &scope_and_user.scope
     );
+    let (queries, request) = match parse_request(&req, &body) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            warn!("invalid graphql request: {}", e);
+            return Ok(bad_request("invalid_request", "BAD_REQUEST"));
+        }
+    };
+    if let Err(e) = check_queries(&queries, &state.settings, &metrics, &scope_and_user) {
+        return Ok(bad_request(&e.to_string(), e.code()));
+    }
     let schema = Arc::clone(&state.schema);
-    graphql_handler(&schema, &(scope_and_user, (*metrics).clone()), req, payload)
-        .await
-        .map_err(|_| ApiError::Unknown)
+    let context = (
+        scope_and_user,
+        (*metrics).clone(),
+        Arc::new(Loader::default()),
+    );
+    let response = request.execute(&schema, &context).await;
+    let status = if response.is_ok() {
+        StatusCode::OK
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+    let mut body = serde_json::to_value(&response).map_err(|e| {
+        error!("unable to serialize graphql response: {}", e);
+        ApiError::Unknown
+    })?;
+    if !state.settings.debug_errors {
+        strip_internal_errors(&mut body);
+    }
+    Ok(HttpResponse::build(status).json(body))
 }
 
+enum WsEvent {
+    Client(Result<Message, actix_ws::ProtocolError>),
+    Server(ServerMessage<DefaultScalarValue>),
+    Done,
+    Expired,
+}
+
+/// Relays graphql-ws messages between the client and juniper. Started
+/// operations are checked against the query limits first and rejected with
+/// an `error` message for their id. Every operation starts with an empty
+/// `loader`. The user is only authenticated on connect, so connections are
+/// closed after `subscription_lifetime_secs`.
+async fn serve_subscriptions<T: AsyncCisClientTrait + Clone + Send + Sync + 'static>(
+    connection: Connection<
+        ArcSchema<Query<T>, Mutation<T>, Subscription<T>, Context, DefaultScalarValue>,
+        ConnectionConfig<Context>,
+    >,
+    mut session: Session,
+    messages: MessageStream,
+    settings: GraphQl,
+    metrics: Arc<Metrics>,
+    scope_and_user: ScopeAndUser,
+    loader: Arc<Loader>,
+) {
+    let (mut to_juniper, from_juniper) = connection.split();
+    let lifetime = Duration::from_secs(settings.subscription_lifetime_secs);
+    let mut events = Box::pin(stream::select(
+        stream::select(
+            messages.map(WsEvent::Client),
+            from_juniper
+                .map(WsEvent::Server)
+                .chain(stream::once(ready(WsEvent::Done))),
+        ),
+        stream::once(actix_rt::time::sleep(lifetime)).map(|_| WsEvent::Expired),
+    ));
+    let reason = loop {
+        let message = match events.next().await {
+            Some(WsEvent::Client(Ok(Message::Text(text)))) => text,
+            Some(WsEvent::Client(Ok(Message::Ping(bytes)))) => {
+                if session.pong(&bytes).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+            Some(WsEvent::Client(Ok(Message::Close(reason)))) => break reason,
+            Some(WsEvent::Client(Ok(_))) => continue,
+            Some(WsEvent::Client(Err(e))) => {
+                warn!("graphql-ws protocol error: {}", e);
+                break Some(CloseReason::from(CloseCode::Protocol));
+            }
+            Some(WsEvent::Server(message)) => {
+                let sent = match serde_json::to_value(&message) {
+                    Ok(mut message) => {
+                        if !settings.debug_errors {
+                            strip_internal_errors(&mut message);
+                        }
+                        session.text(message.to_string()).await.is_ok()
+                    }
+                    Err(e) => {
+                        error!("unable to serialize graphql-ws message: {}", e);
+                        false
+                    }
+                };
+                if !sent {
+                    break None;
+                }
+                continue;
+            }
+            Some(WsEvent::Expired) => {
+                info!("closing expired graphql-ws connection");
+                break Some(CloseReason {
+                    code: CloseCode::Policy,
+                    description: Some(String::from("session expired")),
+                });
+            }
+            Some(WsEvent::Done) | None => break None,
+        };
+        let message: ClientMessage<DefaultScalarValue> = match serde_json::from_str(&message) {
+            Ok(message) => message,
+            Err(e) => {
+                info!("invalid graphql-ws message: {}", e);
+                break Some(CloseReason::from(CloseCode::Invalid));
+            }
+        };
+        if let ClientMessage::Start { id, payload } = &message {
+            if let Err(e) = check_queries(&[&payload.query], &settings, &metrics, &scope_and_user) {
+                let rejected = json!({
+                    "type": "error",
+                    "id": id,
+                    "payload": { "message": e.to_string(), "extensions": { "code": e.code() } }
+                });
+                if session.text(rejected.to_string()).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+            loader.clear();
+        }
+        if to_juniper.send(message).await.is_err() {
+            break None;
+        }
+    };
+    let _ = session.close(reason).await;
+}
+
+/// graphql-ws endpoint for subscriptions.
+#[guard(Authenticated)]
+async fn subscriptions<T: AsyncCisClientTrait + Clone + Send + Sync + 'static>(
+    req: HttpRequest,
+    payload: web::Payload,
+    state: Data<GraphQlState<T>>,
+    scope_and_user: ScopeAndUser,
+    metrics: Data<Metrics>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "graphql subscriptions for {:?} → {:?}",
+        &scope_and_user.user_id, &scope_and_user.scope
+    );
+    let (mut response, session, messages) = actix_ws::handle(&req, payload)
+        .map_err(|e| ApiError::GenericBadRequest(failure::err_msg(e.to_string())))?;
+    response.headers_mut().insert(
+        SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_static("graphql-ws"),
+    );
+    let loader = Arc::new(Loader::default());
+    let context = (
+        scope_and_user.clone(),
+        (*metrics).clone(),
+        Arc::clone(&loader),
+    );
+    let config = ConnectionConfig::new(context).with_keep_alive_interval(KEEP_ALIVE_INTERVAL);
+    let connection = Connection::new(ArcSchema(Arc::clone(&state.schema)), config);
+    actix_rt::spawn(serve_subscriptions(
+        connection,
+        session,
+        messages,
+        state.settings.clone(),
+        (*metrics).clone(),
+        scope_and_user,
+        loader,
+    ));
+    Ok(response)
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn graphql_app<T: AsyncCisClientTrait + Clone + Send + Sync + 'static>(
     cis_client: T,
     dinopark_settings: &DinoParkServices,
+    orgchart_cache: &Arc<OrgchartCache>,
+    outbound: &Arc<Outbound>,
+    username_limiter: &Arc<RateLimiter>,
+    reserved_names: &Arc<ReservedNames>,
+    username_history: &Arc<dyn UsernameHistory>,
+    profile_bus: &Arc<ProfileBus>,
 ) -> impl HttpServiceFactory {
     let schema = Schema::new(
         Query {
             cis_client: cis_client.clone(),
+            username_limiter: Arc::clone(username_limiter),
+            reserved_names: Arc::clone(reserved_names),
+            username_history: Arc::clone(username_history),
+            usernames: dinopark_settings.usernames.clone(),
         },
         Mutation {
-            cis_client,
+            cis_client: cis_client.clone(),
             dinopark_settings: dinopark_settings.clone(),
+            audit_sink: sink_from_settings(&dinopark_settings.audit, outbound.client()),
+            orgchart_cache: Arc::clone(orgchart_cache),
+            outbound: Arc::clone(outbound),
+            reserved_names: Arc::clone(reserved_names),
+            username_history: Arc::clone(username_history),
+            profile_bus: Arc::clone(profile_bus),
+        },
+        Subscription {
+            cis_client,
+            profile_bus: Arc::clone(profile_bus),
         },
-        juniper::EmptySubscription::default(),
     );
 
     web::scope("/graphql")
         .app_data(Data::new(GraphQlState {
             schema: Arc::new(schema),
+            settings: dinopark_settings.graphql.clone(),
         }))
-        .app_data(Data::new(web::JsonConfig::default().limit(1_048_576)))
+        .app_data(web::PayloadConfig::new(1_048_576))
         .service(web::resource("").route(web::post().to(graphql::<T>)))
+        .service(web::resource("/subscriptions").route(web::get().to(subscriptions::<T>)))
         .service(web::resource("/graphiql").route(web::get().to(graphiql)))
 }
@@ -0,0 +1,253 @@
+use crate::settings::Audit;
+use chrono::DateTime;
+use chrono::Utc;
+use cis_profile::schema::Profile;
+use dino_park_trust::Trust;
+use failure::Error;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::error;
+use log::info;
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::Arc;
+
+/// Before/after state of a single profile attribute.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldDiff {
+    pub field: String,
+    pub before: Value,
+    pub after: Value,
+    pub display_before: Value,
+    pub display_after: Value,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AuditEvent {
+    pub user_id: String,
+    pub scope: String,
+    pub timestamp: DateTime<Utc>,
+    pub changes: Vec<FieldDiff>,
+}
+
+pub trait AuditSink: Send + Sync {
+    fn record<'a>(&'a self, event: &'a AuditEvent) -> BoxFuture<'a, Result<(), Error>>;
+}
+
+/// Writes every event as a single JSON log line.
+pub struct LogSink;
+
+impl AuditSink for LogSink {
+    fn record<'a>(&'a self, event: &'a AuditEvent) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            info!("audit: {}", serde_json::to_string(event)?);
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// Posts every event as JSON to an external collector.
+pub struct HttpSink {
+    client: Client,
+    endpoint: String,
+}
+
+impl HttpSink {
+    pub fn new(client: Client, endpoint: String) -> Self {
+        HttpSink { client, endpoint }
+    }
+}
+
+impl AuditSink for HttpSink {
+    fn record<'a>(&'a self, event: &'a AuditEvent) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            self.client
+                .post(&self.endpoint)
+                .json(event)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// Fans out every event to all configured sinks.
+pub struct Sinks(Vec<Box<dyn AuditSink>>);
+
+impl AuditSink for Sinks {
+    fn record<'a>(&'a self, event: &'a AuditEvent) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let mut result = Ok(());
+            for sink in &self.0 {
+                if let Err(e) = sink.record(event).await {
+                    error!("audit sink failed: {}", e);
+                    result = Err(e);
+                }
+            }
+            result
+        }
+        .boxed()
+    }
+}
+
+pub fn sink_from_settings(settings: &Audit, client: &Client) -> Arc<dyn AuditSink> {
+    let mut sinks: Vec<Box<dyn AuditSink>> = vec![];
+    if settings.log_enabled {
+        sinks.push(Box::new(LogSink));
+    }
+    if let Some(endpoint) = &settings.endpoint {
+        sinks.push(Box::new(HttpSink::new(client.clone(), endpoint.clone())));
+    }
+    Arc::new(Sinks(sinks))
+}
+
+fn attribute_value(attribute: &serde_json::Map<String, Value>) -> Value {
+    attribute
+        .get("value")
+        .or_else(|| attribute.get("values"))
+        .cloned()
+        .unwrap_or(Value::Null)
+}
+
+fn attribute_display(attribute: &serde_json::Map<String, Value>) -> Value {
+    attribute
+        .get("metadata")
+        .and_then(|m| m.get("display"))
+        .cloned()
+        .unwrap_or(Value::Null)
+}
+
+fn diff_values(path: &str, before: &Value, after: &Value, diffs: &mut Vec<FieldDiff>) {
+    let (before, after) = match (before, after) {
+        (Value::Object(before), Value::Object(after)) => (before, after),
+        _ => return,
+    };
+    // Every object carrying metadata is an attribute, everything else is
+    // a container of attributes (e.g. identities or access_information).
+    if after.contains_key("metadata") {
+        let diff = FieldDiff {
+            field: path.to_owned(),
+            before: attribute_value(before),
+            after: attribute_value(after),
+            display_before: attribute_display(before),
+            display_after: attribute_display(after),
+        };
+        if diff.before != diff.after || diff.display_before != diff.display_after {
+            diffs.push(diff);
+        }
+        return;
+    }
+    for (key, after_value) in after {
+        let field = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{path}.{key}")
+        };
+        diff_values(
+            &field,
+            before.get(key).unwrap_or(&Value::Null),
+            after_value,
+            diffs,
+        );
+    }
+}
+
+/// Computes the per-attribute changes of value and display level.
+pub fn diff_profiles(before: &Profile, after: &Profile) -> Result<Vec<FieldDiff>, Error> {
+    let mut diffs = vec![];
+    diff_values(
+        "",
+        &serde_json::to_value(before)?,
+        &serde_json::to_value(after)?,
+        &mut diffs,
+    );
+    Ok(diffs)
+}
+
+pub async fn record_update(
+    sink: &dyn AuditSink,
+    user_id: &str,
+    scope: &Trust,
+    before: &Profile,
+    after: &Profile,
+) -> Result<(), Error> {
+    let event = AuditEvent {
+        user_id: user_id.to_owned(),
+        scope: scope.as_str().to_owned(),
+        timestamp: Utc::now(),
+        changes: diff_profiles(before, after)?,
+    };
+    sink.record(&event).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cis_profile::schema::Display;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeSink {
+        events: Mutex<Vec<AuditEvent>>,
+    }
+
+    impl AuditSink for FakeSink {
+        fn record<'a>(&'a self, event: &'a AuditEvent) -> BoxFuture<'a, Result<(), Error>> {
+            self.events.lock().unwrap().push(event.clone());
+            futures::future::ok(()).boxed()
+        }
+    }
+
+    #[test]
+    fn test_diff_unchanged_profile_is_empty() -> Result<(), Error> {
+        let p = Profile::default();
+        assert!(diff_profiles(&p, &p.clone())?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_value_and_display() -> Result<(), Error> {
+        let before = Profile::default();
+        let mut after = before.clone();
+        after.fun_title.value = Some(String::from("Pope"));
+        after.identities.github_id_v3.metadata.display = Some(Display::Vouched);
+        let diffs = diff_profiles(&before, &after)?;
+        assert_eq!(diffs.len(), 2);
+
+        let fun_title = diffs.iter().find(|d| d.field == "fun_title").unwrap();
+        assert_eq!(fun_title.before, Value::Null);
+        assert_eq!(fun_title.after, Value::from("Pope"));
+        assert_eq!(fun_title.display_before, fun_title.display_after);
+
+        let github = diffs
+            .iter()
+            .find(|d| d.field == "identities.github_id_v3")
+            .unwrap();
+        assert_eq!(github.before, github.after);
+        assert_eq!(
+            github.display_after,
+            serde_json::to_value(Display::Vouched)?
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_record_update_writes_to_sink() -> Result<(), Error> {
+        let sink = FakeSink::default();
+        let before = Profile::default();
+        let mut after = before.clone();
+        after.pronouns.value = Some(String::from("they/them"));
+        record_update(&sink, "user1", &Trust::Staff, &before, &after).await?;
+
+        let events = sink.events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].user_id, "user1");
+        assert_eq!(events[0].scope, Trust::Staff.as_str());
+        assert_eq!(events[0].changes.len(), 1);
+        assert_eq!(events[0].changes[0].field, "pronouns");
+        Ok(())
+    }
+}
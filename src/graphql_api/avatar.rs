@@ -1,7 +1,10 @@
 use crate::error::ApiError;
+use crate::metrics::Upstream;
+use crate::outbound::Outbound;
 use cis_profile::schema::Display;
 use failure::Error;
-use reqwest::Client;
+use reqwest::Method;
+use reqwest::StatusCode;
 
 #[derive(Serialize)]
 struct SaveRequest<'a> {
@@ -27,6 +30,7 @@ pub async fn save_picture(
     display: &Display,
     old_url: Option<&str>,
     fossil_send_endpoint: &str,
+    outbound: &Outbound,
 ) -> Result<String, Error> {
     if let Some(intermediate) = update.strip_prefix("intermediate:") {
         let payload = SaveRequest {
@@ -34,10 +38,13 @@ pub async fn save_picture(
             display,
             old_url,
         };
-        let UploadResponse { url } = Client::new()
-            .post(format!("{fossil_send_endpoint}save/{uuid}"))
-            .json(&payload)
-            .send()
+        let UploadResponse { url } = outbound
+            .send(
+                Upstream::Fossil,
+                outbound
+                    .post(format!("{fossil_send_endpoint}save/{uuid}"))
+                    .json(&payload),
+            )
             .await?
             .error_for_status()?
             .json()
@@ -53,15 +60,41 @@ pub async fn change_picture_display(
     display: &Display,
     old_url: Option<&str>,
     fossil_send_endpoint: &str,
+    outbound: &Outbound,
 ) -> Result<String, Error> {
     let payload = ChangeDisplayRequest { display, old_url };
-    let UploadResponse { url } = Client::new()
-        .post(format!("{fossil_send_endpoint}display/{uuid}"))
-        .json(&payload)
-        .send()
+    let UploadResponse { url } = outbound
+        .send(
+            Upstream::Fossil,
+            outbound
+                .post(format!("{fossil_send_endpoint}display/{uuid}"))
+                .json(&payload),
+        )
         .await?
         .error_for_status()?
         .json()
         .await?;
     Ok(url)
 }
+
+/// Removes all sizes of the picture stored for `uuid`. A picture that is
+/// already gone is not an error.
+pub async fn delete_picture(
+    uuid: &str,
+    fossil_send_endpoint: &str,
+    outbound: &Outbound,
+) -> Result<(), Error> {
+    let res = outbound
+        .send(
+            Upstream::Fossil,
+            outbound.request(
+                Method::DELETE,
+                format!("{fossil_send_endpoint}delete/{uuid}"),
+            ),
+        )
+        .await?;
+    if res.status() != StatusCode::NOT_FOUND {
+        res.error_for_status()?;
+    }
+    Ok(())
+}
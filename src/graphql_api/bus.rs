@@ -0,0 +1,179 @@
+use crate::db::tls;
+use deadpool_postgres::Pool;
+use failure::Error;
+use futures::channel::mpsc::channel;
+use futures::channel::mpsc::unbounded;
+use futures::channel::mpsc::Receiver;
+use futures::channel::mpsc::Sender;
+use futures::stream;
+use futures::StreamExt;
+use log::error;
+use log::info;
+use log::warn;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio_postgres::AsyncMessage;
+
+/// Events buffered per subscriber, slow subscribers miss newer events.
+const SUBSCRIBER_BUFFER: usize = 16;
+/// Postgres notification channel shared by all replicas.
+const CHANNEL: &str = "profile_events";
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// A profile has been updated successfully.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProfileEvent {
+    pub user_id: String,
+    pub username: Option<String>,
+    /// Set if the update changed the primary username.
+    pub previous_username: Option<String>,
+}
+
+impl ProfileEvent {
+    pub fn concerns(&self, username: &str) -> bool {
+        self.username.as_deref() == Some(username)
+            || self.previous_username.as_deref() == Some(username)
+    }
+}
+
+/// Fans out profile events to all open subscriptions. With a database,
+/// events go through Postgres `NOTIFY` so subscriptions on every replica are
+/// notified, see [`ProfileBus::listen`].
+#[derive(Default)]
+pub struct ProfileBus {
+    subscribers: Mutex<Vec<Sender<ProfileEvent>>>,
+    pool: Option<Pool>,
+}
+
+impl ProfileBus {
+    pub fn new(pool: Option<Pool>) -> Self {
+        ProfileBus {
+            subscribers: Mutex::new(vec![]),
+            pool,
+        }
+    }
+
+    pub fn subscribe(&self) -> Receiver<ProfileEvent> {
+        let (tx, rx) = channel(SUBSCRIBER_BUFFER);
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    pub async fn publish(&self, event: ProfileEvent) -> Result<(), Error> {
+        match &self.pool {
+            Some(pool) => {
+                let payload = serde_json::to_string(&event)?;
+                pool.get()
+                    .await?
+                    .execute("SELECT pg_notify($1, $2)", &[&CHANNEL, &payload])
+                    .await?;
+            }
+            None => self.deliver(event),
+        }
+        Ok(())
+    }
+
+    /// Hands `event` to the subscriptions of this replica.
+    fn deliver(&self, event: ProfileEvent) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain_mut(|tx| match tx.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(e) => !e.is_disconnected(),
+            });
+    }
+
+    /// Delivers the events published by any replica, reconnecting if the
+    /// connection to `url` is lost. Events published meanwhile are missed.
+    pub async fn listen(self: Arc<Self>, url: String) {
+        loop {
+            if let Err(e) = self.listen_once(&url).await {
+                error!("profile events listener failed: {}", e);
+            }
+            actix_rt::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+
+    async fn listen_once(&self, url: &str) -> Result<(), Error> {
+        let (client, mut connection) = tokio_postgres::connect(url, tls()?).await?;
+        // the connection has to be polled for the client to make progress
+        let (tx, mut notifications) = unbounded();
+        actix_rt::spawn(async move {
+            let mut messages = stream::poll_fn(|cx| connection.poll_message(cx));
+            while let Some(message) = messages.next().await {
+                match message {
+                    Ok(AsyncMessage::Notification(n)) => {
+                        if tx.unbounded_send(n).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("profile events connection failed: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+        client.batch_execute(&format!("LISTEN {}", CHANNEL)).await?;
+        info!("listening for profile events");
+        while let Some(notification) = notifications.next().await {
+            match serde_json::from_str(notification.payload()) {
+                Ok(event) => self.deliver(event),
+                Err(e) => warn!("invalid profile event: {}", e),
+            }
+        }
+        Err(failure::err_msg("connection closed"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn event(user_id: &str) -> ProfileEvent {
+        ProfileEvent {
+            user_id: String::from(user_id),
+            username: Some(String::from("new")),
+            previous_username: Some(String::from("old")),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish_to_all_subscribers() -> Result<(), Error> {
+        let bus = ProfileBus::default();
+        let mut a = bus.subscribe();
+        let mut b = bus.subscribe();
+        bus.publish(event("user1")).await?;
+        assert_eq!(a.next().await, Some(event("user1")));
+        assert_eq!(b.next().await, Some(event("user1")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_closed_subscribers_are_dropped() {
+        let bus = ProfileBus::default();
+        let rx = bus.subscribe();
+        let _open = bus.subscribe();
+        drop(rx);
+        bus.deliver(event("user1"));
+        assert_eq!(bus.subscribers.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_event_round_trips_as_json() -> Result<(), Error> {
+        let json = serde_json::to_string(&event("user1"))?;
+        assert_eq!(serde_json::from_str::<ProfileEvent>(&json)?, event("user1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_concerns_old_and_new_username() {
+        let event = event("user1");
+        assert!(event.concerns("new"));
+        assert!(event.concerns("old"));
+        assert!(!event.concerns("other"));
+    }
+}
@@ -1,6 +1,445 @@
+// DEBT: `failure` derives trigger the `non_local_definitions` lint, see
+// `crate::error`.
+#![allow(non_local_definitions)]
+
+use crate::error::ApiError;
+use cis_client::error::{CisClientError, ProfileError};
+use cis_profile::schema::Display;
+use failure::Fail;
 use juniper::FieldError;
+use juniper::IntoFieldError;
+use juniper::Object;
+use juniper::Value;
+use log::error;
+use serde_json::Value as JsonValue;
+
+/// Extension with internal details, removed from responses by
+/// [`strip_internal_errors`] unless `debug_errors` is enabled.
+const INTERNAL_ERROR: &str = "internal_error";
+
+/// A requested display level which is not allowed for `field`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DisplayViolation {
+    pub field: String,
+    pub display: Display,
+    pub allowed: Vec<Display>,
+}
+
+impl DisplayViolation {
+    pub fn new(field: &str, display: &Display, allowed: &[Display]) -> Self {
+        DisplayViolation {
+            field: field.to_owned(),
+            display: display.clone(),
+            allowed: allowed.to_vec(),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        let mut violation = Object::with_capacity(3);
+        violation.add_field("field", Value::scalar(self.field.clone()));
+        violation.add_field("display", Value::scalar(self.display.as_str().to_owned()));
+        violation.add_field(
+            "allowed",
+            Value::list(
+                self.allowed
+                    .iter()
+                    .map(|display| Value::scalar(display.as_str().to_owned()))
+                    .collect(),
+            ),
+        );
+        Value::object(violation)
+    }
+}
+
+/// A value rejected by the content checks for `field`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContentViolation {
+    pub field: String,
+    pub reason: &'static str,
+}
+
+impl ContentViolation {
+    pub fn new(field: &str, reason: &'static str) -> Self {
+        ContentViolation {
+            field: field.to_owned(),
+            reason,
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        let mut violation = Object::with_capacity(2);
+        violation.add_field("field", Value::scalar(self.field.clone()));
+        violation.add_field("reason", Value::scalar(self.reason.to_owned()));
+        Value::object(violation)
+    }
+}
+
+#[derive(Fail, Debug)]
+pub enum UpdateError {
+    /// Every problem found in an update, all of them are reported at once.
+    #[fail(display = "invalid update")]
+    Invalid {
+        display: Vec<DisplayViolation>,
+        content: Vec<ContentViolation>,
+        not_editable: Vec<String>,
+    },
+}
+
+#[derive(Fail, Debug)]
+pub enum QueryLimitError {
+    #[fail(display = "unable to analyze query")]
+    Invalid,
+    #[fail(display = "query exceeds maximum depth of {}", _0)]
+    TooDeep(usize),
+    #[fail(display = "query exceeds maximum complexity of {}", _0)]
+    TooComplex(usize),
+    #[fail(display = "query exceeds maximum of {} aliases", _0)]
+    TooManyAliases(usize),
+    #[fail(display = "batch exceeds maximum of {} queries", _0)]
+    BatchTooLarge(usize),
+}
+
+impl QueryLimitError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QueryLimitError::Invalid => "invalid",
+            QueryLimitError::TooDeep(_) => "depth",
+            QueryLimitError::TooComplex(_) => "complexity",
+            QueryLimitError::TooManyAliases(_) => "aliases",
+            QueryLimitError::BatchTooLarge(_) => "batch",
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            QueryLimitError::Invalid => "INVALID_QUERY",
+            QueryLimitError::TooDeep(_) => "QUERY_TOO_DEEP",
+            QueryLimitError::TooComplex(_) => "QUERY_TOO_COMPLEX",
+            QueryLimitError::TooManyAliases(_) => "TOO_MANY_ALIASES",
+            QueryLimitError::BatchTooLarge(_) => "BATCH_TOO_LARGE",
+        }
+    }
+}
+
+/// Errors returned by resolvers. Clients match on the `code` extension, the
+/// message is kept for older clients.
+///
+/// Deliberately not `Display`: juniper turns anything displayable into a
+/// plain `FieldError` without extensions.
+#[derive(Debug)]
+pub enum GraphQlError {
+    UsernameLength(&'static str),
+    UsernameInvalidChars(&'static str),
+    UsernameReserved,
+    UsernameTaken,
+    InvalidUpdate {
+        display: Vec<DisplayViolation>,
+        content: Vec<ContentViolation>,
+        not_editable: Vec<String>,
+    },
+    InvalidViewAs,
+    ProfileNotReady,
+    ProfileNotFound,
+    Forbidden(&'static str),
+    BatchTooLarge(usize),
+    RateLimited(i32),
+    UpstreamUnavailable(failure::Error),
+    Internal(&'static str, failure::Error),
+}
+
+pub type GraphQlResult<T> = Result<T, GraphQlError>;
+
+impl GraphQlError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            GraphQlError::UsernameLength(_) | GraphQlError::UsernameInvalidChars(_) => {
+                "USERNAME_INVALID"
+            }
+            GraphQlError::UsernameReserved => "USERNAME_RESERVED",
+            GraphQlError::UsernameTaken => "USERNAME_TAKEN",
+            GraphQlError::InvalidUpdate {
+                display,
+                not_editable,
+                ..
+            } => {
+                if !not_editable.is_empty() {
+                    "FIELD_NOT_EDITABLE"
+                } else if !display.is_empty() {
+                    "INVALID_DISPLAY"
+                } else {
+                    "INVALID_CONTENT"
+                }
+            }
+            GraphQlError::InvalidViewAs => "INVALID_DISPLAY",
+            GraphQlError::ProfileNotReady => "PROFILE_NOT_READY",
+            GraphQlError::ProfileNotFound => "PROFILE_NOT_FOUND",
+            GraphQlError::Forbidden(_) => "FORBIDDEN",
+            GraphQlError::BatchTooLarge(_) => "BATCH_TOO_LARGE",
+            GraphQlError::RateLimited(_) => "RATE_LIMITED",
+            GraphQlError::UpstreamUnavailable(_) => "UPSTREAM_UNAVAILABLE",
+            GraphQlError::Internal(..) => "INTERNAL",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            GraphQlError::UsernameLength(_) => "username_length",
+            GraphQlError::UsernameInvalidChars(_) => "username_invalid_chars",
+            GraphQlError::UsernameReserved => "username_reserved",
+            GraphQlError::UsernameTaken => "username_exists",
+            GraphQlError::InvalidUpdate {
+                display,
+                not_editable,
+                ..
+            } => {
+                if !not_editable.is_empty() {
+                    "field_not_editable"
+                } else if !display.is_empty() {
+                    "invalid_display"
+                } else {
+                    "invalid_content"
+                }
+            }
+            GraphQlError::InvalidViewAs => "invalid_view_as",
+            GraphQlError::ProfileNotReady => "wait_for_profile",
+            GraphQlError::ProfileNotFound => "profile_does_not_exist",
+            GraphQlError::Forbidden(_) => "forbidden",
+            GraphQlError::BatchTooLarge(_) => "batch_too_large",
+            GraphQlError::RateLimited(_) => "rate_limited",
+            GraphQlError::UpstreamUnavailable(_) => "upstream_unavailable",
+            GraphQlError::Internal(msg, _) => *msg,
+        }
+    }
+
+    fn internal_error(&self) -> Option<String> {
+        match self {
+            GraphQlError::Forbidden(detail) => Some((*detail).to_owned()),
+            GraphQlError::UpstreamUnavailable(e) | GraphQlError::Internal(_, e) => {
+                Some(e.to_string())
+            }
+            _ => None,
+        }
+    }
+
+    fn to_field_error(&self) -> FieldError {
+        let mut extensions = Object::with_capacity(4);
+        extensions.add_field("code", Value::scalar(self.code().to_owned()));
+        match self {
+            GraphQlError::UsernameLength(hint) | GraphQlError::UsernameInvalidChars(hint) => {
+                extensions.add_field("hint", Value::scalar((*hint).to_owned()));
+            }
+            GraphQlError::BatchTooLarge(max) => {
+                extensions.add_field(
+                    "max",
+                    Value::scalar(i32::try_from(*max).unwrap_or(i32::MAX)),
+                );
+            }
+            GraphQlError::RateLimited(retry_after) => {
+                extensions.add_field("retry_after", Value::scalar(*retry_after));
+            }
+            GraphQlError::InvalidUpdate {
+                display,
+                content,
+                not_editable,
+            } => {
+                extensions.add_field(
+                    "display_violations",
+                    Value::list(display.iter().map(DisplayViolation::to_value).collect()),
+                );
+                extensions.add_field(
+                    "content_violations",
+                    Value::list(content.iter().map(ContentViolation::to_value).collect()),
+                );
+                extensions.add_field(
+                    "fields",
+                    Value::list(
+                        not_editable
+                            .iter()
+                            .map(|field| Value::scalar(field.clone()))
+                            .collect(),
+                    ),
+                );
+            }
+            _ => {}
+        }
+        if let Some(internal_error) = self.internal_error() {
+            extensions.add_field(INTERNAL_ERROR, Value::scalar(internal_error));
+        }
+        FieldError::new(self.message(), Value::object(extensions))
+    }
+}
+
+impl IntoFieldError for GraphQlError {
+    fn into_field_error(self) -> FieldError {
+        if let GraphQlError::UpstreamUnavailable(e) | GraphQlError::Internal(_, e) = &self {
+            error!("{}: {}", self.message(), e);
+        }
+        self.to_field_error()
+    }
+}
+
+/// Removes internal error details from a serialized (batch or graphql-ws)
+/// response.
+pub fn strip_internal_errors(response: &mut JsonValue) {
+    match response {
+        JsonValue::Array(responses) => responses.iter_mut().for_each(strip_internal_errors),
+        JsonValue::Object(response) => {
+            if let Some(JsonValue::Array(errors)) = response.get_mut("errors") {
+                for error in errors {
+                    if let Some(JsonValue::Object(extensions)) = error.get_mut("extensions") {
+                        extensions.remove(INTERNAL_ERROR);
+                    }
+                }
+            }
+            if let Some(payload) = response.get_mut("payload") {
+                strip_internal_errors(payload);
+            }
+        }
+        _ => {}
+    }
+}
+
+impl From<CisClientError> for GraphQlError {
+    fn from(e: CisClientError) -> Self {
+        match e {
+            CisClientError::ProfileError(ProfileError::ProfileDoesNotExist) => {
+                GraphQlError::ProfileNotFound
+            }
+            e => GraphQlError::UpstreamUnavailable(e.into()),
+        }
+    }
+}
+
+/// For errors from applying an [`crate::graphql_api::input::InputProfile`],
+/// failing calls to CIS or Fossil are reported as upstream errors.
+impl From<failure::Error> for GraphQlError {
+    fn from(e: failure::Error) -> Self {
+        let e = match e.downcast::<UpdateError>() {
+            Ok(UpdateError::Invalid {
+                display,
+                content,
+                not_editable,
+            }) => {
+                return GraphQlError::InvalidUpdate {
+                    display,
+                    content,
+                    not_editable,
+                }
+            }
+            Err(e) => e,
+        };
+        let e = match e.downcast::<CisClientError>() {
+            Ok(e) => return e.into(),
+            Err(e) => e,
+        };
+        match e.downcast_ref::<ApiError>() {
+            Some(
+                ApiError::ProxyError
+                | ApiError::UpstreamClientError(_)
+                | ApiError::UpstreamAuthError(_)
+                | ApiError::PayloadTooLarge
+                | ApiError::UpstreamUnavailable(_),
+            ) => GraphQlError::UpstreamUnavailable(e),
+            _ if e.downcast_ref::<reqwest::Error>().is_some() => {
+                GraphQlError::UpstreamUnavailable(e)
+            }
+            _ => GraphQlError::Internal("unable update/sign profile", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_code_and_message() {
+        let e = GraphQlError::from(failure::Error::from(UpdateError::Invalid {
+            display: vec![DisplayViolation::new(
+                "fun_title",
+                &Display::Private,
+                &[Display::Staff],
+            )],
+            content: vec![ContentViolation::new("timezone", "not an IANA time zone")],
+            not_editable: vec![],
+        }));
+        let field_error = e.to_field_error();
+        assert_eq!(field_error.message(), "invalid_display");
+        assert_eq!(
+            field_error.extensions(),
+            &graphql_value!({
+                "code": "INVALID_DISPLAY",
+                "display_violations": [
+                    { "field": "fun_title", "display": "private", "allowed": ["staff"] }
+                ],
+                "content_violations": [
+                    { "field": "timezone", "reason": "not an IANA time zone" }
+                ],
+                "fields": []
+            })
+        );
+    }
+
+    #[test]
+    fn test_upstream_errors_are_not_internal() {
+        let e = GraphQlError::from(failure::Error::from(ApiError::UpstreamUnavailable(
+            "fossil",
+        )));
+        assert_eq!(e.code(), "UPSTREAM_UNAVAILABLE");
+        let e = GraphQlError::from(failure::Error::from(ApiError::ProxyError));
+        assert_eq!(e.code(), "UPSTREAM_UNAVAILABLE");
+        let e = GraphQlError::from(failure::Error::from(CisClientError::ProfileError(
+            ProfileError::ProfileDoesNotExist,
+        )));
+        assert_eq!(e.code(), "PROFILE_NOT_FOUND");
+        let e = GraphQlError::from(failure::Error::from(ApiError::Unknown));
+        assert_eq!(e.code(), "INTERNAL");
+        let e = GraphQlError::from(failure::err_msg("unable to sign"));
+        assert_eq!(e.code(), "INTERNAL");
+    }
+
+    #[test]
+    fn test_internal_error_is_stripped() {
+        let e = GraphQlError::Internal("unable to diff profile", failure::err_msg("secret"));
+        assert_eq!(
+            e.to_field_error().extensions(),
+            &graphql_value!({ "code": "INTERNAL", "internal_error": "secret" })
+        );
+        let error = json!({
+            "message": "x",
+            "extensions": { "code": "INTERNAL", "internal_error": "secret" }
+        });
+        let mut response = json!([
+            { "data": null, "errors": [error.clone()] },
+            { "type": "data", "id": "1", "payload": { "data": null, "errors": [error] } }
+        ]);
+        strip_internal_errors(&mut response);
+        let stripped = json!({ "message": "x", "extensions": { "code": "INTERNAL" } });
+        assert_eq!(
+            response,
+            json!([
+                { "data": null, "errors": [stripped.clone()] },
+                { "type": "data", "id": "1", "payload": { "data": null, "errors": [stripped] } }
+            ])
+        );
+    }
+
+    #[test]
+    fn test_invalid_username_has_hint() {
+        assert_eq!(
+            GraphQlError::UsernameLength("2 to 64 characters")
+                .to_field_error()
+                .extensions(),
+            &graphql_value!({ "code": "USERNAME_INVALID", "hint": "2 to 64 characters" })
+        );
+    }
 
-pub fn field_error(msg: &str, e: impl std::fmt::Display) -> FieldError {
-    let error = format!("{msg}: {e}");
-    FieldError::new(msg, graphql_value!({ "internal_error": error }))
+    #[test]
+    fn test_rate_limited_keeps_retry_after() {
+        assert_eq!(
+            GraphQlError::RateLimited(3).to_field_error().extensions(),
+            &graphql_value!({ "code": "RATE_LIMITED", "retry_after": 3 })
+        );
+    }
 }
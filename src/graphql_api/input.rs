@@ -1,8 +1,13 @@
 use crate::graphql_api::avatar::change_picture_display;
 use crate::graphql_api::avatar::save_picture;
+use crate::graphql_api::error::ContentViolation;
+use crate::graphql_api::error::DisplayViolation;
+use crate::graphql_api::error::UpdateError;
+use crate::outbound::Outbound;
 use crate::settings::Fossil;
 use chrono::DateTime;
 use chrono::Utc;
+use chrono_tz::Tz;
 use cis_profile::crypto::Signer;
 use cis_profile::schema::AccessInformationProviderSubObject;
 use cis_profile::schema::Display;
@@ -13,10 +18,12 @@ use cis_profile::schema::PublisherAuthority;
 use cis_profile::schema::StandardAttributeString;
 use cis_profile::schema::StandardAttributeValues;
 use dino_park_trust::Trust;
-use failure::format_err;
 use failure::Error;
+use juniper::GraphQLEnum;
 use juniper::GraphQLInputObject;
+use juniper::GraphQLObject;
 use std::collections::BTreeMap;
+use url::Url;
 
 const DISPLAY_ANY: &[Display; 6] = &[
     Display::Private,
@@ -37,21 +44,529 @@ const DISPLAY_NOT_PRIVATE: &[Display; 5] = &[
 
 const DISPLAY_PRIVATE_STAFF: &[Display; 2] = &[Display::Private, Display::Staff];
 
+const ALLOWED_URI_SCHEMES: &[&str] = &["https", "http"];
+
+type ContentCheck = fn(&str) -> Result<(), &'static str>;
+
+/// Content checks for the values of a field. Keys of key-value fields are
+/// limited to `MAX_KEY_LENGTH`.
+struct ContentRule {
+    max_length: usize,
+    multiline: bool,
+    check: Option<ContentCheck>,
+}
+
+const MAX_KEY_LENGTH: usize = 256;
+
+/// What the owner may change of an attribute, the mozilliansorg publisher
+/// signs the result.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Editable {
+    ValueAndDisplay,
+    Display,
+}
+
+/// Editability, allowed display levels and content checks of an attribute
+/// of an `InputProfile`, `validate` and `apply` both use these.
+struct FieldRule {
+    field: &'static str,
+    editable: Editable,
+    display: &'static [Display],
+    content: Option<ContentRule>,
+}
+
+const ALTERNATIVE_NAME: FieldRule = FieldRule {
+    field: "alternative_name",
+    editable: Editable::ValueAndDisplay,
+    display: DISPLAY_NOT_PRIVATE,
+    content: Some(ContentRule {
+        max_length: 256,
+        multiline: false,
+        check: None,
+    }),
+};
+
+const CREATED: FieldRule = FieldRule {
+    field: "created",
+    editable: Editable::Display,
+    display: DISPLAY_NOT_PRIVATE,
+    content: None,
+};
+
+const CUSTOM_1_PRIMARY_EMAIL: FieldRule = FieldRule {
+    field: "identities.custom_1_primary_email",
+    editable: Editable::ValueAndDisplay,
+    display: DISPLAY_ANY,
+    content: None,
+};
+
+const CUSTOM_2_PRIMARY_EMAIL: FieldRule = FieldRule {
+    field: "identities.custom_2_primary_email",
+    editable: Editable::ValueAndDisplay,
+    display: DISPLAY_ANY,
+    content: None,
+};
+
+const DESCRIPTION: FieldRule = FieldRule {
+    field: "description",
+    editable: Editable::ValueAndDisplay,
+    display: DISPLAY_NOT_PRIVATE,
+    content: Some(ContentRule {
+        max_length: 2_000,
+        multiline: true,
+        check: None,
+    }),
+};
+
+const FIRST_NAME: FieldRule = FieldRule {
+    field: "first_name",
+    editable: Editable::ValueAndDisplay,
+    display: DISPLAY_NOT_PRIVATE,
+    content: Some(ContentRule {
+        max_length: 256,
+        multiline: false,
+        check: None,
+    }),
+};
+
+const FUN_TITLE: FieldRule = FieldRule {
+    field: "fun_title",
+    editable: Editable::ValueAndDisplay,
+    display: DISPLAY_NOT_PRIVATE,
+    content: Some(ContentRule {
+        max_length: 256,
+        multiline: false,
+        check: None,
+    }),
+};
+
+const LAST_MODIFIED: FieldRule = FieldRule {
+    field: "last_modified",
+    editable: Editable::Display,
+    display: DISPLAY_NOT_PRIVATE,
+    content: None,
+};
+
+const LAST_NAME: FieldRule = FieldRule {
+    field: "last_name",
+    editable: Editable::ValueAndDisplay,
+    display: DISPLAY_NOT_PRIVATE,
+    content: Some(ContentRule {
+        max_length: 256,
+        multiline: false,
+        check: None,
+    }),
+};
+
+const LOCATION: FieldRule = FieldRule {
+    field: "location",
+    editable: Editable::ValueAndDisplay,
+    display: DISPLAY_NOT_PRIVATE,
+    content: Some(ContentRule {
+        max_length: 256,
+        multiline: false,
+        check: None,
+    }),
+};
+
+const LOGIN_METHOD: FieldRule = FieldRule {
+    field: "login_method",
+    editable: Editable::Display,
+    display: DISPLAY_ANY,
+    content: None,
+};
+
+const PICTURE: FieldRule = FieldRule {
+    field: "picture",
+    editable: Editable::ValueAndDisplay,
+    display: DISPLAY_NOT_PRIVATE,
+    content: None,
+};
+
+const PRIMARY_USERNAME: FieldRule = FieldRule {
+    field: "primary_username",
+    editable: Editable::ValueAndDisplay,
+    display: &[Display::Public],
+    content: None,
+};
+
+const PRONOUNS: FieldRule = FieldRule {
+    field: "pronouns",
+    editable: Editable::ValueAndDisplay,
+    display: DISPLAY_NOT_PRIVATE,
+    content: Some(ContentRule {
+        max_length: 64,
+        multiline: false,
+        check: None,
+    }),
+};
+
+const TIMEZONE: FieldRule = FieldRule {
+    field: "timezone",
+    editable: Editable::ValueAndDisplay,
+    display: DISPLAY_NOT_PRIVATE,
+    content: Some(ContentRule {
+        max_length: 64,
+        multiline: false,
+        check: Some(check_timezone),
+    }),
+};
+
+const USER_ID: FieldRule = FieldRule {
+    field: "user_id",
+    editable: Editable::Display,
+    display: DISPLAY_NOT_PRIVATE,
+    content: None,
+};
+
+const LANGUAGES: FieldRule = FieldRule {
+    field: "languages",
+    editable: Editable::ValueAndDisplay,
+    display: DISPLAY_NOT_PRIVATE,
+    content: Some(ContentRule {
+        max_length: 64,
+        multiline: false,
+        check: Some(check_language),
+    }),
+};
+
+const PHONE_NUMBERS: FieldRule = FieldRule {
+    field: "phone_numbers",
+    editable: Editable::ValueAndDisplay,
+    display: DISPLAY_ANY,
+    content: Some(ContentRule {
+        max_length: 32,
+        multiline: false,
+        check: Some(check_phone_number),
+    }),
+};
+
+const TAGS: FieldRule = FieldRule {
+    field: "tags",
+    editable: Editable::ValueAndDisplay,
+    display: DISPLAY_NOT_PRIVATE,
+    content: Some(ContentRule {
+        max_length: 64,
+        multiline: false,
+        check: None,
+    }),
+};
+
+const URIS: FieldRule = FieldRule {
+    field: "uris",
+    editable: Editable::ValueAndDisplay,
+    display: DISPLAY_NOT_PRIVATE,
+    content: Some(ContentRule {
+        max_length: 2_048,
+        multiline: false,
+        check: Some(check_uri),
+    }),
+};
+
+const USERNAMES: FieldRule = FieldRule {
+    field: "usernames",
+    editable: Editable::ValueAndDisplay,
+    display: DISPLAY_NOT_PRIVATE,
+    content: Some(ContentRule {
+        max_length: 256,
+        multiline: false,
+        check: None,
+    }),
+};
+
+/// Staff may not hide their primary email.
+const PRIMARY_EMAIL_STAFF: FieldRule = FieldRule {
+    field: "primary_email",
+    editable: Editable::Display,
+    display: DISPLAY_NOT_PRIVATE,
+    content: None,
+};
+
+const PRIMARY_EMAIL: FieldRule = FieldRule {
+    field: "primary_email",
+    editable: Editable::Display,
+    display: DISPLAY_ANY,
+    content: None,
+};
+
+const PGP_PUBLIC_KEYS: FieldRule = FieldRule {
+    field: "pgp_public_keys",
+    editable: Editable::Display,
+    display: DISPLAY_ANY,
+    content: None,
+};
+
+const SSH_PUBLIC_KEYS: FieldRule = FieldRule {
+    field: "ssh_public_keys",
+    editable: Editable::Display,
+    display: DISPLAY_ANY,
+    content: None,
+};
+
+const ACCESS_INFORMATION_MOZILLIANSORG: FieldRule = FieldRule {
+    field: "access_information.mozilliansorg",
+    editable: Editable::Display,
+    display: DISPLAY_NOT_PRIVATE,
+    content: None,
+};
+
+const ACCESS_INFORMATION_LDAP: FieldRule = FieldRule {
+    field: "access_information.ldap",
+    editable: Editable::Display,
+    display: DISPLAY_PRIVATE_STAFF,
+    content: None,
+};
+
+const STAFF_INFORMATION_TITLE: FieldRule = FieldRule {
+    field: "staff_information.title",
+    editable: Editable::Display,
+    display: DISPLAY_NOT_PRIVATE,
+    content: None,
+};
+
+const STAFF_INFORMATION_OFFICE_LOCATION: FieldRule = FieldRule {
+    field: "staff_information.office_location",
+    editable: Editable::Display,
+    display: DISPLAY_NOT_PRIVATE,
+    content: None,
+};
+
+const IDENTITIES_GITHUB: FieldRule = FieldRule {
+    field: "identities.github",
+    editable: Editable::Display,
+    display: DISPLAY_NOT_PRIVATE,
+    content: None,
+};
+
+const IDENTITIES_BUGZILLA: FieldRule = FieldRule {
+    field: "identities.bugzilla",
+    editable: Editable::Display,
+    display: DISPLAY_NOT_PRIVATE,
+    content: None,
+};
+
+const IDENTITIES_GOOGLE: FieldRule = FieldRule {
+    field: "identities.google",
+    editable: Editable::Display,
+    display: DISPLAY_NOT_PRIVATE,
+    content: None,
+};
+
+fn primary_email_rule(scope: &Trust) -> &'static FieldRule {
+    if scope == &Trust::Staff {
+        &PRIMARY_EMAIL_STAFF
+    } else {
+        &PRIMARY_EMAIL
+    }
+}
+
+fn check_timezone(value: &str) -> Result<(), &'static str> {
+    value
+        .parse::<Tz>()
+        .map(|_| ())
+        .map_err(|_| "not an IANA time zone")
+}
+
+/// Syntax of a BCP 47 language tag, e.g. `en` or `zh-Hant-TW`.
+fn check_language(value: &str) -> Result<(), &'static str> {
+    let mut subtags = value.split('-');
+    let language = subtags.next().unwrap_or_default();
+    let valid = (2..=8).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err("not a BCP 47 language tag")
+    }
+}
+
+/// E.164 numbers with optional formatting, e.g. `+49 (30) 123-456`.
+fn check_phone_number(value: &str) -> Result<(), &'static str> {
+    let number = value.strip_prefix('+').unwrap_or(value);
+    let digits = number.chars().filter(char::is_ascii_digit).count();
+    if number
+        .chars()
+        .all(|c| c.is_ascii_digit() || " -().".contains(c))
+        && (7..=15).contains(&digits)
+    {
+        Ok(())
+    } else {
+        Err("not a valid phone number")
+    }
+}
+
+fn check_uri(value: &str) -> Result<(), &'static str> {
+    let url = Url::parse(value).map_err(|_| "not a valid URL")?;
+    if ALLOWED_URI_SCHEMES.contains(&url.scheme()) {
+        Ok(())
+    } else {
+        Err("URL scheme not allowed")
+    }
+}
+
+/// Strips control characters, keeping line breaks in multiline fields.
+fn clean(rule: &FieldRule, value: &str) -> String {
+    let multiline = rule
+        .content
+        .as_ref()
+        .map(|content| content.multiline)
+        .unwrap_or_default();
+    value
+        .chars()
+        .filter(|c| !c.is_control() || (multiline && *c == '\n'))
+        .collect()
+}
+
+/// Checks a cleaned key and value, see `clean`.
+fn check_content(
+    rule: &FieldRule,
+    key: Option<&str>,
+    value: Option<&str>,
+    violations: &mut Vec<ContentViolation>,
+) {
+    let content = match &rule.content {
+        Some(content) => content,
+        None => return,
+    };
+    let path = match key {
+        Some(key) => format!("{}.{key}", rule.field),
+        None => rule.field.to_owned(),
+    };
+    if key.is_some_and(|key| key.chars().count() > MAX_KEY_LENGTH) {
+        violations.push(ContentViolation::new(&path, "key too long"));
+    }
+    let value = match value {
+        Some(value) if !value.is_empty() => value,
+        _ => return,
+    };
+    if value.chars().count() > content.max_length {
+        violations.push(ContentViolation::new(&path, "too long"));
+    } else if let Some(Err(reason)) = content.check.map(|check| check(&value)) {
+        violations.push(ContentViolation::new(&path, reason));
+    }
+}
+
+#[derive(GraphQLEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    Value,
+    Display,
+    RemovedIdentity,
+    /// The attribute was re-signed by the mozilliansorg publisher without
+    /// any change to its value or display level.
+    Signed,
+}
+
+impl ChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeKind::Value => "value",
+            ChangeKind::Display => "display",
+            ChangeKind::RemovedIdentity => "remove",
+            ChangeKind::Signed => "signed",
+        }
+    }
+}
+
+#[derive(GraphQLObject, Clone, Debug, PartialEq)]
+pub struct FieldChange {
+    pub field: String,
+    pub kind: ChangeKind,
+}
+
+#[derive(Default, Debug)]
+pub struct ChangeReport {
+    pub changes: Vec<FieldChange>,
+    pub warnings: Vec<String>,
+    /// Uuid whose pictures have to be deleted from fossil once the updated
+    /// profile has been stored.
+    pub delete_picture: Option<String>,
+}
+
+impl ChangeReport {
+    /// Records a change once per field and kind, e.g. attributes updated
+    /// through a deprecated and a current input.
+    fn push(&mut self, field: &str, kind: ChangeKind) {
+        let change = FieldChange {
+            field: field.to_owned(),
+            kind,
+        };
+        if !self.changes.contains(&change) {
+            self.changes.push(change);
+        }
+    }
+
+    pub fn warn(&mut self, field: &str, warning: &str) {
+        self.warnings.push(format!("{field}: {warning}"));
+    }
+
+    pub fn changed(&self) -> bool {
+        !self.changes.is_empty()
+    }
+}
+
 fn create_usernames_key(typ: &str) -> String {
     format!("HACK#{typ}")
 }
 
+fn invalid_display(rule: &FieldRule, display: &Display) -> Error {
+    UpdateError::Invalid {
+        display: vec![DisplayViolation::new(rule.field, display, rule.display)],
+        content: vec![],
+        not_editable: vec![],
+    }
+    .into()
+}
+
+fn check_editable(rule: &FieldRule, value_changed: bool, not_editable: &mut Vec<String>) {
+    if value_changed && rule.editable != Editable::ValueAndDisplay {
+        not_editable.push(rule.field.to_owned());
+    }
+}
+
+/// Rule, requested update and current attribute of a field.
+type FieldUpdate<'a, U, C> = (&'a FieldRule, &'a U, &'a C);
+
+fn check_display(
+    rule: &FieldRule,
+    requested: Option<&Display>,
+    current: &Option<Display>,
+    violations: &mut Vec<DisplayViolation>,
+) {
+    if let Some(display) = requested {
+        if Some(display) != current.as_ref() && !rule.display.contains(display) {
+            violations.push(DisplayViolation::new(rule.field, display, rule.display));
+        }
+    }
+}
+
+fn check_identity_display(
+    rule: &FieldRule,
+    identity: Option<&IdentityWithDisplay>,
+    current: &[&Option<Display>],
+    violations: &mut Vec<DisplayViolation>,
+) {
+    if let Some(identity) = identity.filter(|i| !i.remove.unwrap_or_default()) {
+        if current.iter().any(|display| **display != identity.display) {
+            check_display(rule, identity.display.as_ref(), &None, violations);
+        }
+    }
+}
+
 fn update_access_information_display(
-    d: &Option<Display>,
+    rule: &FieldRule,
+    d: Option<&Display>,
     p: &mut AccessInformationProviderSubObject,
     now: &DateTime<Utc>,
     store: &impl Signer,
-    allowed: &[Display],
-) -> Result<bool, Error> {
-    if *d != p.metadata.display {
-        if let Some(display) = &d {
-            if !allowed.contains(display) {
-                return Err(format_err!("invalid display level"));
+    report: &mut ChangeReport,
+) -> Result<(), Error> {
+    if d != p.metadata.display.as_ref() {
+        if let Some(display) = d {
+            if !rule.display.contains(display) {
+                return Err(invalid_display(rule, display));
             }
             // Initialize with empty values if there are now access groups.
             if p.values.is_none() {
@@ -61,12 +576,13 @@ fn update_access_information_display(
             p.metadata.last_modified = *now;
             p.signature.publisher.name = PublisherAuthority::Mozilliansorg;
             store.sign_attribute(p)?;
-            return Ok(true);
+            report.push(rule.field, ChangeKind::Display);
         }
     }
-    Ok(false)
+    Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn update_picture(
     s: &Option<StringWithDisplay>,
     p: &mut StandardAttributeString,
@@ -74,44 +590,69 @@ async fn update_picture(
     now: &DateTime<Utc>,
     store: &impl Signer,
     fossil_settings: &Fossil,
-) -> Result<bool, Error> {
+    outbound: &Outbound,
+    dry_run: bool,
+    report: &mut ChangeReport,
+) -> Result<(), Error> {
     let mut changed = false;
     if let Some(new_picture) = s {
         if new_picture.display != p.metadata.display {
             if let Some(display) = &new_picture.display {
-                if !DISPLAY_NOT_PRIVATE.contains(display) {
-                    return Err(format_err!("invalid display level"));
+                if !PICTURE.display.contains(display) {
+                    return Err(invalid_display(&PICTURE, display));
                 }
                 // if display changed but field is null change it to empty string
                 if p.value.is_none() {
                     p.value = Some(String::default());
                 }
                 p.metadata.display = Some(display.clone());
+                report.push(PICTURE.field, ChangeKind::Display);
                 changed = true;
             }
         }
         if new_picture.value != p.value && new_picture.value != Some(String::default()) {
             if let Some(display) = &p.metadata.display {
                 if let Some(value) = &new_picture.value {
-                    let uuid = uuid
-                        .value
-                        .as_ref()
-                        .ok_or_else(|| failure::err_msg("no uuid in profile"))?;
-                    let url = save_picture(
-                        value,
-                        uuid,
-                        display,
-                        p.value.as_deref(),
-                        &fossil_settings.upload_endpoint,
-                    )
-                    .await?;
+                    let url = if dry_run {
+                        // a preview must never store anything in fossil
+                        value.clone()
+                    } else {
+                        let uuid = uuid
+                            .value
+                            .as_ref()
+                            .ok_or_else(|| failure::err_msg("no uuid in profile"))?;
+                        save_picture(
+                            value,
+                            uuid,
+                            display,
+                            p.value.as_deref(),
+                            &fossil_settings.upload_endpoint,
+                            outbound,
+                        )
+                        .await?
+                    };
                     p.value = Some(url);
+                    report.push(PICTURE.field, ChangeKind::Value);
                     changed = true;
                 }
             }
+        } else if new_picture.value != p.value && new_picture.value == Some(String::default()) {
+            // Only delete from fossil after CIS accepted the update, otherwise
+            // the profile would point to a missing picture.
+            if !dry_run
+                && p.value
+                    .as_deref()
+                    .map(|v| !v.is_empty())
+                    .unwrap_or_default()
+            {
+                report.delete_picture = uuid.value.clone();
+            }
+            p.value = new_picture.value.clone();
+            report.push(PICTURE.field, ChangeKind::Value);
+            changed = true;
         } else if changed && p.value != Some(String::default()) {
             // if only the display level changed we have to send a display update to fossil
-            if let Some(display) = &p.metadata.display {
+            if let (false, Some(display)) = (dry_run, &p.metadata.display) {
                 let uuid = uuid
                     .value
                     .as_ref()
@@ -121,15 +662,11 @@ async fn update_picture(
                     display,
                     p.value.as_deref(),
                     &fossil_settings.upload_endpoint,
+                    outbound,
                 )
                 .await?;
                 p.value = Some(url);
-                changed = true;
             }
-        } else if new_picture.value != p.value && new_picture.value == Some(String::default()) {
-            // TODO: delete picture
-            p.value = new_picture.value.clone();
-            changed = true;
         }
 
         if changed {
@@ -138,7 +675,7 @@ async fn update_picture(
             store.sign_attribute(p)?;
         }
     }
-    Ok(changed)
+    Ok(())
 }
 
 fn update_google_identity(
@@ -146,21 +683,22 @@ fn update_google_identity(
     p: &mut IdentitiesAttributesValuesArray,
     now: &DateTime<Utc>,
     store: &impl Signer,
-) -> Result<bool, Error> {
-    let mut changed_google = false;
+    report: &mut ChangeReport,
+) -> Result<(), Error> {
+    let mut changed_google = None;
     if google.remove.unwrap_or_default() {
         p.google_oauth2_id.metadata.display = Some(Display::Staff);
         p.google_primary_email.metadata.display = Some(Display::Staff);
 
         p.google_oauth2_id.value = Some(String::default());
         p.google_primary_email.value = Some(String::default());
-        changed_google = true;
+        changed_google = Some(ChangeKind::RemovedIdentity);
     } else if google.display != p.google_oauth2_id.metadata.display
         || google.display != p.google_primary_email.metadata.display
     {
         if let Some(display) = &google.display {
-            if !DISPLAY_NOT_PRIVATE.contains(display) {
-                return Err(format_err!("invalid display level"));
+            if !IDENTITIES_GOOGLE.display.contains(display) {
+                return Err(invalid_display(&IDENTITIES_GOOGLE, display));
             }
             if p.google_oauth2_id.value.is_none() {
                 p.google_oauth2_id.value = Some(String::default())
@@ -171,20 +709,22 @@ fn update_google_identity(
 
             p.google_oauth2_id.metadata.display = Some(display.clone());
             p.google_primary_email.metadata.display = Some(display.clone());
-            changed_google = true;
+            changed_google = Some(ChangeKind::Display);
         }
     }
 
-    if changed_google {
+    if let Some(kind) = changed_google {
         p.google_oauth2_id.metadata.last_modified = *now;
         p.google_primary_email.metadata.last_modified = now.to_owned();
         p.google_oauth2_id.signature.publisher.name = PublisherAuthority::Mozilliansorg;
         p.google_primary_email.signature.publisher.name = PublisherAuthority::Mozilliansorg;
         store.sign_attribute(&mut p.google_oauth2_id)?;
         store.sign_attribute(&mut p.google_primary_email)?;
+        report.push("identities.google_oauth2_id", kind);
+        report.push("identities.google_primary_email", kind);
     }
 
-    Ok(changed_google)
+    Ok(())
 }
 
 fn update_bugzilla_identity(
@@ -193,8 +733,9 @@ fn update_bugzilla_identity(
     u: &mut StandardAttributeValues,
     now: &DateTime<Utc>,
     store: &impl Signer,
-) -> Result<bool, Error> {
-    let mut changed_bugzilla = false;
+    report: &mut ChangeReport,
+) -> Result<(), Error> {
+    let mut changed_bugzilla = None;
     let mut changed_usernames = false;
     if bugzilla.remove.unwrap_or_default() {
         p.bugzilla_mozilla_org_id.metadata.display = Some(Display::Staff);
@@ -211,13 +752,13 @@ fn update_bugzilla_identity(
 
         p.bugzilla_mozilla_org_id.value = Some(String::default());
         p.bugzilla_mozilla_org_primary_email.value = Some(String::default());
-        changed_bugzilla = true;
+        changed_bugzilla = Some(ChangeKind::RemovedIdentity);
     } else if bugzilla.display != p.bugzilla_mozilla_org_id.metadata.display
         || bugzilla.display != p.bugzilla_mozilla_org_primary_email.metadata.display
     {
         if let Some(display) = &bugzilla.display {
-            if !DISPLAY_NOT_PRIVATE.contains(display) {
-                return Err(format_err!("invalid display level"));
+            if !IDENTITIES_BUGZILLA.display.contains(display) {
+                return Err(invalid_display(&IDENTITIES_BUGZILLA, display));
             }
             if p.bugzilla_mozilla_org_id.value.is_none() {
                 p.bugzilla_mozilla_org_id.value = Some(String::default())
@@ -228,11 +769,11 @@ fn update_bugzilla_identity(
 
             p.bugzilla_mozilla_org_id.metadata.display = Some(display.clone());
             p.bugzilla_mozilla_org_primary_email.metadata.display = Some(display.clone());
-            changed_bugzilla = true;
+            changed_bugzilla = Some(ChangeKind::Display);
         }
     }
 
-    if changed_bugzilla {
+    if let Some(kind) = changed_bugzilla {
         p.bugzilla_mozilla_org_id.metadata.last_modified = *now;
         p.bugzilla_mozilla_org_primary_email.metadata.last_modified = now.to_owned();
         p.bugzilla_mozilla_org_id.signature.publisher.name = PublisherAuthority::Mozilliansorg;
@@ -242,15 +783,18 @@ fn update_bugzilla_identity(
             .name = PublisherAuthority::Mozilliansorg;
         store.sign_attribute(&mut p.bugzilla_mozilla_org_id)?;
         store.sign_attribute(&mut p.bugzilla_mozilla_org_primary_email)?;
+        report.push("identities.bugzilla_mozilla_org_id", kind);
+        report.push("identities.bugzilla_mozilla_org_primary_email", kind);
     }
 
     if changed_usernames {
         u.metadata.last_modified = *now;
         u.signature.publisher.name = PublisherAuthority::Mozilliansorg;
         store.sign_attribute(u)?;
+        report.push(USERNAMES.field, ChangeKind::Value);
     }
 
-    Ok(changed_bugzilla || changed_usernames)
+    Ok(())
 }
 
 fn update_github_identity(
@@ -259,8 +803,9 @@ fn update_github_identity(
     u: &mut StandardAttributeValues,
     now: &DateTime<Utc>,
     store: &impl Signer,
-) -> Result<bool, Error> {
-    let mut changed_github = false;
+    report: &mut ChangeReport,
+) -> Result<(), Error> {
+    let mut changed_github = None;
     let mut changed_usernames = false;
     if github.remove.unwrap_or_default() {
         p.github_id_v3.metadata.display = Some(Display::Staff);
@@ -276,14 +821,14 @@ fn update_github_identity(
         p.github_id_v3.value = Some(String::default());
         p.github_id_v4.value = Some(String::default());
         p.github_primary_email.value = Some(String::default());
-        changed_github = true;
+        changed_github = Some(ChangeKind::RemovedIdentity);
     } else if github.display != p.github_id_v3.metadata.display
         || github.display != p.github_id_v4.metadata.display
         || github.display != p.github_primary_email.metadata.display
     {
         if let Some(display) = &github.display {
-            if !DISPLAY_NOT_PRIVATE.contains(display) {
-                return Err(format_err!("invalid display level"));
+            if !IDENTITIES_GITHUB.display.contains(display) {
+                return Err(invalid_display(&IDENTITIES_GITHUB, display));
             }
             if p.github_id_v3.value.is_none() {
                 p.github_id_v3.value = Some(String::default())
@@ -298,11 +843,11 @@ fn update_github_identity(
             p.github_id_v3.metadata.display = Some(display.clone());
             p.github_id_v4.metadata.display = Some(display.clone());
             p.github_primary_email.metadata.display = Some(display.clone());
-            changed_github = true;
+            changed_github = Some(ChangeKind::Display);
         }
     }
 
-    if changed_github {
+    if let Some(kind) = changed_github {
         p.github_id_v3.metadata.last_modified = *now;
         p.github_id_v4.metadata.last_modified = *now;
         p.github_primary_email.metadata.last_modified = now.to_owned();
@@ -312,15 +857,19 @@ fn update_github_identity(
         store.sign_attribute(&mut p.github_id_v3)?;
         store.sign_attribute(&mut p.github_id_v4)?;
         store.sign_attribute(&mut p.github_primary_email)?;
+        report.push("identities.github_id_v3", kind);
+        report.push("identities.github_id_v4", kind);
+        report.push("identities.github_primary_email", kind);
     }
 
     if changed_usernames {
         u.metadata.last_modified = *now;
         u.signature.publisher.name = PublisherAuthority::Mozilliansorg;
         store.sign_attribute(u)?;
+        report.push(USERNAMES.field, ChangeKind::Value);
     }
 
-    Ok(changed_github || changed_usernames)
+    Ok(())
 }
 
 fn update_identities(
@@ -329,40 +878,46 @@ fn update_identities(
     u: &mut StandardAttributeValues,
     now: &DateTime<Utc>,
     store: &impl Signer,
-) -> Result<bool, Error> {
-    let mut changed = false;
+    report: &mut ChangeReport,
+) -> Result<(), Error> {
     if let Some(identities) = i {
         if let Some(github) = &identities.github {
-            changed |= update_github_identity(github, p, u, now, store)?;
+            update_github_identity(github, p, u, now, store, report)?;
         }
         if let Some(bugzilla) = &identities.bugzilla {
-            changed |= update_bugzilla_identity(bugzilla, p, u, now, store)?;
+            update_bugzilla_identity(bugzilla, p, u, now, store, report)?;
         }
         if let Some(google) = &identities.google {
-            changed |= update_google_identity(google, p, now, store)?;
+            update_google_identity(google, p, now, store, report)?;
         }
     }
 
-    Ok(changed)
+    Ok(())
 }
 
 fn update_display_for_string(
+    rule: &FieldRule,
     d: &Option<Display>,
     p: &mut StandardAttributeString,
     now: &DateTime<Utc>,
     store: &impl Signer,
-    allowed: &[Display],
-) -> Result<bool, Error> {
+    report: &mut ChangeReport,
+) -> Result<(), Error> {
     let mut changed = false;
     if d != &p.metadata.display {
         if let Some(display) = &d {
-            if !allowed.contains(display) {
-                return Err(format_err!("invalid display level"));
+            if !rule.display.contains(display) {
+                return Err(invalid_display(rule, display));
             }
             // if display changed but field is null we cannot do anything
             if p.value.is_some() {
                 p.metadata.display = Some(display.clone());
                 changed = true;
+            } else {
+                report.warn(
+                    rule.field,
+                    "display level ignored for attribute without value",
+                );
             }
         }
     }
@@ -370,27 +925,34 @@ fn update_display_for_string(
         p.metadata.last_modified = *now;
         p.signature.publisher.name = PublisherAuthority::Mozilliansorg;
         store.sign_attribute(p)?;
+        report.push(rule.field, ChangeKind::Display);
     }
-    Ok(changed)
+    Ok(())
 }
 
 fn update_display_for_key_values(
+    rule: &FieldRule,
     d: &Option<Display>,
     p: &mut StandardAttributeValues,
     now: &DateTime<Utc>,
     store: &impl Signer,
-    allowed: &[Display],
-) -> Result<bool, Error> {
+    report: &mut ChangeReport,
+) -> Result<(), Error> {
     let mut changed = false;
     if d != &p.metadata.display {
         if let Some(display) = &d {
-            if !allowed.contains(display) {
-                return Err(format_err!("invalid display level"));
+            if !rule.display.contains(display) {
+                return Err(invalid_display(rule, display));
             }
             // if display changed but field is null change it to empty string
             if p.values.is_some() {
                 p.metadata.display = Some(display.clone());
                 changed = true;
+            } else {
+                report.warn(
+                    rule.field,
+                    "display level ignored for attribute without values",
+                );
             }
         }
     }
@@ -398,35 +960,40 @@ fn update_display_for_key_values(
         p.metadata.last_modified = *now;
         p.signature.publisher.name = PublisherAuthority::Mozilliansorg;
         store.sign_attribute(p)?;
+        report.push(rule.field, ChangeKind::Display);
     }
-    Ok(changed)
+    Ok(())
 }
 
 fn update_string(
+    rule: &FieldRule,
     s: &Option<StringWithDisplay>,
     p: &mut StandardAttributeString,
     now: &DateTime<Utc>,
     store: &impl Signer,
-    allowed: &[Display],
-) -> Result<bool, Error> {
+    report: &mut ChangeReport,
+) -> Result<(), Error> {
     let mut changed = false;
     if let Some(x) = s {
-        if x.value != p.value {
-            if let Some(value) = &x.value {
-                p.value = Some(value.clone());
+        let value = x.value.as_deref().map(|v| clean(rule, v));
+        if value != p.value {
+            if let Some(value) = value {
+                p.value = Some(value);
+                report.push(rule.field, ChangeKind::Value);
                 changed = true;
             }
         }
         if x.display != p.metadata.display {
             if let Some(display) = &x.display {
-                if !allowed.contains(display) {
-                    return Err(format_err!("invalid display level"));
+                if !rule.display.contains(display) {
+                    return Err(invalid_display(rule, display));
                 }
                 // if display changed but field is null change it to empty string
                 if p.value.is_none() {
                     p.value = Some(String::default());
                 }
                 p.metadata.display = Some(display.clone());
+                report.push(rule.field, ChangeKind::Display);
                 changed = true;
             }
         }
@@ -436,50 +1003,51 @@ fn update_string(
             store.sign_attribute(p)?;
         }
     }
-    Ok(changed)
+    Ok(())
 }
 
 fn update_key_values(
+    rule: &FieldRule,
     s: &Option<KeyValuesWithDisplay>,
     p: &mut StandardAttributeValues,
     now: &DateTime<Utc>,
     store: &impl Signer,
     filter_empty_values: bool,
-    allowed: &[Display],
-) -> Result<bool, Error> {
+    report: &mut ChangeReport,
+) -> Result<(), Error> {
     let mut changed = false;
+    let mut values_changed = false;
+    let mut display_changed = false;
     if let Some(x) = s {
         if let Some(values) = &x.values {
+            let values = values
+                .iter()
+                .map(|e| (clean(rule, &e.k), e.v.as_deref().map(|v| clean(rule, v))));
             let values: BTreeMap<String, Option<String>> = if filter_empty_values {
                 values
-                    .iter()
-                    .filter_map(|e| {
-                        if e.v.as_ref().map(|s| s.is_empty()).unwrap_or_default() {
-                            None
-                        } else {
-                            Some((e.k.clone(), e.v.clone()))
-                        }
-                    })
+                    .filter(|(_, v)| !v.as_ref().map(|s| s.is_empty()).unwrap_or_default())
                     .collect()
             } else {
-                values.iter().map(|e| (e.k.clone(), e.v.clone())).collect()
+                values.collect()
             };
             let kv = Some(KeyValue(values));
             if kv != p.values {
                 p.values = kv;
+                values_changed = true;
             }
             changed = true;
         }
         if x.display != p.metadata.display {
             if let Some(display) = &x.display {
-                if !allowed.contains(display) {
-                    return Err(format_err!("invalid display level"));
+                if !rule.display.contains(display) {
+                    return Err(invalid_display(rule, display));
                 }
                 // if display changed but field is null change it to empty dict
                 if p.values.is_none() {
                     p.values = Some(KeyValue(BTreeMap::default()));
                 }
                 p.metadata.display = Some(display.clone());
+                display_changed = true;
                 changed = true;
             }
         }
@@ -487,9 +1055,18 @@ fn update_key_values(
             p.metadata.last_modified = *now;
             p.signature.publisher.name = PublisherAuthority::Mozilliansorg;
             store.sign_attribute(p)?;
+            if values_changed {
+                report.push(rule.field, ChangeKind::Value);
+            }
+            if display_changed {
+                report.push(rule.field, ChangeKind::Display);
+            }
+            if !values_changed && !display_changed {
+                report.push(rule.field, ChangeKind::Signed);
+            }
         }
     }
-    Ok(changed)
+    Ok(())
 }
 
 #[derive(GraphQLInputObject, Default)]
@@ -535,6 +1112,7 @@ pub struct InputProfile {
     // TODO: delete after upgrade
     pub access_information_mozilliansorg: Option<Display>,
     pub access_information_mozilliansorg_display: Option<Display>,
+    /// Ignored, `active` is managed by CIS.
     pub active: Option<BoolWithDisplay>,
     pub alternative_name: Option<StringWithDisplay>,
     pub created: Option<StringWithDisplay>,
@@ -572,242 +1150,486 @@ impl InputProfile {
         scope: &Trust,
         secret_store: &impl Signer,
         fossil_settings: &Fossil,
-    ) -> Result<bool, Error> {
+        outbound: &Outbound,
+    ) -> Result<ChangeReport, Error> {
+        self.apply(p, scope, secret_store, fossil_settings, outbound, false)
+            .await
+    }
+
+    /// Applies the update like `update_profile` without storing pictures in
+    /// fossil, so the result can be shown to the user before committing.
+    pub async fn preview_profile(
+        &self,
+        p: &mut Profile,
+        scope: &Trust,
+        secret_store: &impl Signer,
+        fossil_settings: &Fossil,
+        outbound: &Outbound,
+    ) -> Result<ChangeReport, Error> {
+        self.apply(p, scope, secret_store, fossil_settings, outbound, true)
+            .await
+    }
+
+    /// The display level requested for the mozilliansorg access groups,
+    /// preferring the current over the deprecated input.
+    fn access_information_mozilliansorg_display(&self) -> Option<&Display> {
+        // TODO: delete after upgrade
+        self.access_information_mozilliansorg_display
+            .as_ref()
+            .or(self.access_information_mozilliansorg.as_ref())
+    }
+
+    /// String attributes with their rule, requested update and current value.
+    fn strings<'a>(
+        &'a self,
+        p: &'a Profile,
+    ) -> [FieldUpdate<'a, Option<StringWithDisplay>, StandardAttributeString>; 16] {
+        [
+            (
+                &ALTERNATIVE_NAME,
+                &self.alternative_name,
+                &p.alternative_name,
+            ),
+            (&CREATED, &self.created, &p.created),
+            (
+                &CUSTOM_1_PRIMARY_EMAIL,
+                &self.custom_1_primary_email,
+                &p.identities.custom_1_primary_email,
+            ),
+            (
+                &CUSTOM_2_PRIMARY_EMAIL,
+                &self.custom_2_primary_email,
+                &p.identities.custom_2_primary_email,
+            ),
+            (&DESCRIPTION, &self.description, &p.description),
+            (&FIRST_NAME, &self.first_name, &p.first_name),
+            (&FUN_TITLE, &self.fun_title, &p.fun_title),
+            (&LAST_MODIFIED, &self.last_modified, &p.last_modified),
+            (&LAST_NAME, &self.last_name, &p.last_name),
+            (&LOCATION, &self.location, &p.location),
+            (&LOGIN_METHOD, &self.login_method, &p.login_method),
+            (&PICTURE, &self.picture, &p.picture),
+            (
+                &PRIMARY_USERNAME,
+                &self.primary_username,
+                &p.primary_username,
+            ),
+            (&PRONOUNS, &self.pronouns, &p.pronouns),
+            (&TIMEZONE, &self.timezone, &p.timezone),
+            (&USER_ID, &self.user_id, &p.user_id),
+        ]
+    }
+
+    /// Key-value attributes with their rule, requested update and current
+    /// values.
+    fn key_values<'a>(
+        &'a self,
+        p: &'a Profile,
+    ) -> [FieldUpdate<'a, Option<KeyValuesWithDisplay>, StandardAttributeValues>; 5] {
+        [
+            (&LANGUAGES, &self.languages, &p.languages),
+            (&PHONE_NUMBERS, &self.phone_numbers, &p.phone_numbers),
+            (&TAGS, &self.tags, &p.tags),
+            (&USERNAMES, &self.usernames, &p.usernames),
+            (&URIS, &self.uris, &p.uris),
+        ]
+    }
+
+    /// Collects every write to an attribute which is not `editable`, every
+    /// requested display level not allowed by its `FieldRule` and every value
+    /// failing its content checks before anything gets signed or stored.
+    fn validate(&self, p: &Profile, scope: &Trust) -> Result<(), UpdateError> {
+        let mut violations = vec![];
+        let mut content_violations = vec![];
+        let mut not_editable = vec![];
+        for (rule, update, current) in self.strings(p) {
+            let value = update
+                .as_ref()
+                .and_then(|u| u.value.as_deref())
+                .map(|v| clean(rule, v));
+            let changed = value.is_some() && value != current.value;
+            check_editable(rule, changed, &mut not_editable);
+            check_display(
+                rule,
+                update.as_ref().and_then(|u| u.display.as_ref()),
+                &current.metadata.display,
+                &mut violations,
+            );
+            // Stored values predating the content checks must not block
+            // updates of other fields.
+            if changed {
+                check_content(rule, None, value.as_deref(), &mut content_violations);
+            }
+        }
+        for (rule, update, current) in self.key_values(p) {
+            check_display(
+                rule,
+                update.as_ref().and_then(|u| u.display.as_ref()),
+                &current.metadata.display,
+                &mut violations,
+            );
+            check_editable(
+                rule,
+                update.as_ref().is_some_and(|u| u.values.is_some()),
+                &mut not_editable,
+            );
+            let current_values = current.values.as_ref().map(|KeyValue(values)| values);
+            for kv in update.iter().flat_map(|u| u.values.iter().flatten()) {
+                let key = clean(rule, &kv.k);
+                let value = kv.v.as_deref().map(|v| clean(rule, v));
+                if current_values.and_then(|values| values.get(&key)) != Some(&value) {
+                    check_content(rule, Some(&key), value.as_deref(), &mut content_violations);
+                }
+            }
+        }
+        let displays: &[(&FieldRule, Option<&Display>, &Option<Display>)] = &[
+            (
+                primary_email_rule(scope),
+                self.primary_email_display.as_ref(),
+                &p.primary_email.metadata.display,
+            ),
+            (
+                &PGP_PUBLIC_KEYS,
+                self.pgp_public_keys_display.as_ref(),
+                &p.pgp_public_keys.metadata.display,
+            ),
+            (
+                &SSH_PUBLIC_KEYS,
+                self.ssh_public_keys_display.as_ref(),
+                &p.ssh_public_keys.metadata.display,
+            ),
+            (
+                &ACCESS_INFORMATION_MOZILLIANSORG,
+                self.access_information_mozilliansorg_display(),
+                &p.access_information.mozilliansorg.metadata.display,
+            ),
+            (
+                &ACCESS_INFORMATION_LDAP,
+                self.access_information_ldap_display.as_ref(),
+                &p.access_information.ldap.metadata.display,
+            ),
+            (
+                &STAFF_INFORMATION_TITLE,
+                self.staff_information_title_display.as_ref(),
+                &p.staff_information.title.metadata.display,
+            ),
+            (
+                &STAFF_INFORMATION_OFFICE_LOCATION,
+                self.staff_information_office_location_display.as_ref(),
+                &p.staff_information.office_location.metadata.display,
+            ),
+        ];
+        for (rule, update, current) in displays {
+            check_display(rule, *update, current, &mut violations);
+        }
+        if let Some(identities) = &self.identities {
+            let i = &p.identities;
+            check_identity_display(
+                &IDENTITIES_GITHUB,
+                identities.github.as_ref(),
+                &[
+                    &i.github_id_v3.metadata.display,
+                    &i.github_id_v4.metadata.display,
+                    &i.github_primary_email.metadata.display,
+                ],
+                &mut violations,
+            );
+            check_identity_display(
+                &IDENTITIES_BUGZILLA,
+                identities.bugzilla.as_ref(),
+                &[
+                    &i.bugzilla_mozilla_org_id.metadata.display,
+                    &i.bugzilla_mozilla_org_primary_email.metadata.display,
+                ],
+                &mut violations,
+            );
+            check_identity_display(
+                &IDENTITIES_GOOGLE,
+                identities.google.as_ref(),
+                &[
+                    &i.google_oauth2_id.metadata.display,
+                    &i.google_primary_email.metadata.display,
+                ],
+                &mut violations,
+            );
+        }
+        if not_editable.is_empty() && violations.is_empty() && content_violations.is_empty() {
+            Ok(())
+        } else {
+            Err(UpdateError::Invalid {
+                display: violations,
+                content: content_violations,
+                not_editable,
+            })
+        }
+    }
+
+    async fn apply(
+        &self,
+        p: &mut Profile,
+        scope: &Trust,
+        secret_store: &impl Signer,
+        fossil_settings: &Fossil,
+        outbound: &Outbound,
+        dry_run: bool,
+    ) -> Result<ChangeReport, Error> {
+        self.validate(p, scope)?;
         let now = &Utc::now();
-        let mut changed = false;
-        changed |= update_string(
+        let mut report = ChangeReport::default();
+        update_string(
+            &ALTERNATIVE_NAME,
             &self.alternative_name,
             &mut p.alternative_name,
             now,
             secret_store,
-            DISPLAY_NOT_PRIVATE,
+            &mut report,
         )?;
-        changed |= update_string(
+        update_string(
+            &CREATED,
             &self.created,
             &mut p.created,
             now,
             secret_store,
-            DISPLAY_NOT_PRIVATE,
+            &mut report,
         )?;
-        changed |= update_string(
+        update_string(
+            &CUSTOM_1_PRIMARY_EMAIL,
             &self.custom_1_primary_email,
             &mut p.identities.custom_1_primary_email,
             now,
             secret_store,
-            DISPLAY_ANY,
+            &mut report,
         )?;
-        changed |= update_string(
+        update_string(
+            &CUSTOM_2_PRIMARY_EMAIL,
             &self.custom_2_primary_email,
             &mut p.identities.custom_2_primary_email,
             now,
             secret_store,
-            DISPLAY_ANY,
+            &mut report,
         )?;
-        changed |= update_string(
+        update_string(
+            &DESCRIPTION,
             &self.description,
             &mut p.description,
             now,
             secret_store,
-            DISPLAY_NOT_PRIVATE,
+            &mut report,
         )?;
-        changed |= update_string(
+        update_string(
+            &FIRST_NAME,
             &self.first_name,
             &mut p.first_name,
             now,
             secret_store,
-            DISPLAY_NOT_PRIVATE,
+            &mut report,
         )?;
-        changed |= update_string(
+        update_string(
+            &FUN_TITLE,
             &self.fun_title,
             &mut p.fun_title,
             now,
             secret_store,
-            DISPLAY_NOT_PRIVATE,
+            &mut report,
         )?;
-        changed |= update_string(
+        update_string(
+            &LAST_MODIFIED,
             &self.last_modified,
             &mut p.last_modified,
             now,
             secret_store,
-            DISPLAY_NOT_PRIVATE,
+            &mut report,
         )?;
-        changed |= update_string(
+        update_string(
+            &LAST_NAME,
             &self.last_name,
             &mut p.last_name,
             now,
             secret_store,
-            DISPLAY_NOT_PRIVATE,
+            &mut report,
         )?;
-        changed |= update_string(
+        update_string(
+            &LOCATION,
             &self.location,
             &mut p.location,
             now,
             secret_store,
-            DISPLAY_NOT_PRIVATE,
+            &mut report,
         )?;
-        changed |= update_string(
+        update_string(
+            &LOGIN_METHOD,
             &self.login_method,
             &mut p.login_method,
             now,
             secret_store,
-            DISPLAY_ANY,
+            &mut report,
         )?;
-        changed |= update_picture(
+        update_picture(
             &self.picture,
             &mut p.picture,
             &p.uuid,
             now,
             secret_store,
             fossil_settings,
+            outbound,
+            dry_run,
+            &mut report,
         )
         .await?;
-        changed |= update_display_for_string(
+        update_display_for_string(
+            primary_email_rule(scope),
             &self.primary_email_display,
             &mut p.primary_email,
             now,
             secret_store,
-            if scope == &Trust::Staff {
-                DISPLAY_NOT_PRIVATE
-            } else {
-                DISPLAY_ANY
-            },
+            &mut report,
         )?;
-        changed |= update_string(
+        update_string(
+            &PRIMARY_USERNAME,
             &self.primary_username,
             &mut p.primary_username,
             now,
             secret_store,
-            &[Display::Public],
+            &mut report,
         )?;
-        changed |= update_string(
+        update_string(
+            &PRONOUNS,
             &self.pronouns,
             &mut p.pronouns,
             now,
             secret_store,
-            DISPLAY_NOT_PRIVATE,
+            &mut report,
         )?;
-        changed |= update_string(
+        update_string(
+            &TIMEZONE,
             &self.timezone,
             &mut p.timezone,
             now,
             secret_store,
-            DISPLAY_NOT_PRIVATE,
+            &mut report,
         )?;
-        changed |= update_string(
+        update_string(
+            &USER_ID,
             &self.user_id,
             &mut p.user_id,
             now,
             secret_store,
-            DISPLAY_NOT_PRIVATE,
+            &mut report,
         )?;
 
-        changed |= update_key_values(
+        update_key_values(
+            &LANGUAGES,
             &self.languages,
             &mut p.languages,
             now,
             secret_store,
             false,
-            DISPLAY_NOT_PRIVATE,
+            &mut report,
         )?;
-        changed |= update_key_values(
+        update_key_values(
+            &PHONE_NUMBERS,
             &self.phone_numbers,
             &mut p.phone_numbers,
             now,
             secret_store,
             true,
-            DISPLAY_ANY,
+            &mut report,
         )?;
-        changed |= update_key_values(
+        update_key_values(
+            &TAGS,
             &self.tags,
             &mut p.tags,
             now,
             secret_store,
             false,
-            DISPLAY_NOT_PRIVATE,
+            &mut report,
         )?;
-        changed |= update_key_values(
+        update_key_values(
+            &USERNAMES,
             &self.usernames,
             &mut p.usernames,
             now,
             secret_store,
             true,
-            DISPLAY_NOT_PRIVATE,
+            &mut report,
         )?;
-        changed |= update_key_values(
+        update_key_values(
+            &URIS,
             &self.uris,
             &mut p.uris,
             now,
             secret_store,
             true,
-            DISPLAY_NOT_PRIVATE,
+            &mut report,
         )?;
-        changed |= update_display_for_key_values(
+        update_display_for_key_values(
+            &PGP_PUBLIC_KEYS,
             &self.pgp_public_keys_display,
             &mut p.pgp_public_keys,
             now,
             secret_store,
-            DISPLAY_ANY,
+            &mut report,
         )?;
-        changed |= update_display_for_key_values(
+        update_display_for_key_values(
+            &SSH_PUBLIC_KEYS,
             &self.ssh_public_keys_display,
             &mut p.ssh_public_keys,
             now,
             secret_store,
-            DISPLAY_ANY,
+            &mut report,
         )?;
-        changed |= update_identities(
+        update_identities(
             &self.identities,
             &mut p.identities,
             &mut p.usernames,
             now,
             secret_store,
+            &mut report,
         )?;
 
-        // TODO: delete after upgrade
-        changed |= update_access_information_display(
-            &self.access_information_mozilliansorg,
+        update_access_information_display(
+            &ACCESS_INFORMATION_MOZILLIANSORG,
+            self.access_information_mozilliansorg_display(),
             &mut p.access_information.mozilliansorg,
             now,
             secret_store,
-            DISPLAY_NOT_PRIVATE,
+            &mut report,
         )?;
-        changed |= update_access_information_display(
-            &self.access_information_mozilliansorg_display,
-            &mut p.access_information.mozilliansorg,
-            now,
-            secret_store,
-            DISPLAY_NOT_PRIVATE,
-        )?;
-        changed |= update_access_information_display(
-            &self.access_information_ldap_display,
+        update_access_information_display(
+            &ACCESS_INFORMATION_LDAP,
+            self.access_information_ldap_display.as_ref(),
             &mut p.access_information.ldap,
             now,
             secret_store,
-            DISPLAY_PRIVATE_STAFF,
+            &mut report,
         )?;
-        changed |= update_display_for_string(
+        update_display_for_string(
+            &STAFF_INFORMATION_TITLE,
             &self.staff_information_title_display,
             &mut p.staff_information.title,
             now,
             secret_store,
-            DISPLAY_NOT_PRIVATE,
+            &mut report,
         )?;
-        changed |= update_display_for_string(
+        update_display_for_string(
+            &STAFF_INFORMATION_OFFICE_LOCATION,
             &self.staff_information_office_location_display,
             &mut p.staff_information.office_location,
             now,
             secret_store,
-            DISPLAY_NOT_PRIVATE,
+            &mut report,
         )?;
-        Ok(changed)
+        Ok(report)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::metrics::Metrics;
     use cis_profile::crypto::SecretStore;
     use cis_profile::schema::Profile;
+    use reqwest::Client;
 
     fn get_fake_secret_store() -> SecretStore {
         let v = vec![(
@@ -819,12 +1641,18 @@ mod test {
             .unwrap()
     }
 
-    #[tokio::test]
-    async fn test_simple_update() -> Result<(), Error> {
-        let secret_store = get_fake_secret_store();
+    /// Signing keys, an outbound client and a Fossil which can't be reached.
+    fn setup() -> Result<(SecretStore, Outbound, Fossil), Error> {
+        let outbound = Outbound::new(Client::new(), &Default::default(), &Metrics::new()?);
         let fossil_settings = Fossil {
-            upload_endpoint: String::default(),
+            upload_endpoint: String::from("http://fossil.invalid/"),
         };
+        Ok((get_fake_secret_store(), outbound, fossil_settings))
+    }
+
+    #[tokio::test]
+    async fn test_simple_update() -> Result<(), Error> {
+        let (secret_store, outbound, fossil_settings) = setup()?;
         let mut p = Profile::default();
         let update = InputProfile {
             fun_title: Some(StringWithDisplay {
@@ -835,7 +1663,13 @@ mod test {
         };
         assert_eq!(p.fun_title.value, None);
         update
-            .update_profile(&mut p, &Trust::Staff, &secret_store, &fossil_settings)
+            .update_profile(
+                &mut p,
+                &Trust::Staff,
+                &secret_store,
+                &fossil_settings,
+                &outbound,
+            )
             .await?;
         assert_eq!(p.fun_title.value, update.fun_title.unwrap().value);
         Ok(())
@@ -843,10 +1677,7 @@ mod test {
 
     #[tokio::test]
     async fn test_update_with_invalid_display_fails() -> Result<(), Error> {
-        let secret_store = get_fake_secret_store();
-        let fossil_settings = Fossil {
-            upload_endpoint: String::default(),
-        };
+        let (secret_store, outbound, fossil_settings) = setup()?;
         let mut p = Profile::default();
         let update = InputProfile {
             fun_title: Some(StringWithDisplay {
@@ -859,18 +1690,392 @@ mod test {
         assert_eq!(p.fun_title.value, None);
         assert_ne!(p.fun_title.metadata.display, Some(Display::Private));
         assert!(update
-            .update_profile(&mut p, &Trust::Staff, &secret_store, &fossil_settings)
+            .update_profile(
+                &mut p,
+                &Trust::Staff,
+                &secret_store,
+                &fossil_settings,
+                &outbound
+            )
             .await
             .is_err());
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_update_display_only_with_null_value_string() -> Result<(), Error> {
-        let secret_store = get_fake_secret_store();
-        let fossil_settings = Fossil {
-            upload_endpoint: String::default(),
+    async fn test_update_collects_all_invalid_displays() -> Result<(), Error> {
+        let (secret_store, outbound, fossil_settings) = setup()?;
+        let mut p = Profile::default();
+        let update = InputProfile {
+            fun_title: Some(StringWithDisplay {
+                value: Some(String::from("Pope")),
+                display: Some(Display::Private),
+            }),
+            pronouns: Some(StringWithDisplay {
+                value: Some(String::from("they/them")),
+                display: Some(Display::Staff),
+            }),
+            primary_username: Some(StringWithDisplay {
+                value: None,
+                display: Some(Display::Staff),
+            }),
+            ..InputProfile::default()
         };
+        let e = update
+            .update_profile(
+                &mut p,
+                &Trust::Staff,
+                &secret_store,
+                &fossil_settings,
+                &outbound,
+            )
+            .await
+            .unwrap_err();
+        let UpdateError::Invalid { display, .. } = e.downcast::<UpdateError>()?;
+        assert_eq!(
+            display,
+            vec![
+                DisplayViolation::new("fun_title", &Display::Private, DISPLAY_NOT_PRIVATE),
+                DisplayViolation::new("primary_username", &Display::Staff, &[Display::Public]),
+            ]
+        );
+        // nothing is applied if any field is invalid
+        assert_eq!(p.pronouns.value, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_rules() {
+        let mut violations = vec![];
+        check_content(
+            &URIS,
+            Some("EA#WEB#1"),
+            Some("https://mozilla.org"),
+            &mut violations,
+        );
+        check_content(
+            &URIS,
+            Some("EA#WEB#2"),
+            Some("javascript:alert(1)"),
+            &mut violations,
+        );
+        check_content(
+            &PHONE_NUMBERS,
+            Some("p1"),
+            Some("+49 (30) 1234-56"),
+            &mut violations,
+        );
+        check_content(&PHONE_NUMBERS, Some("p2"), Some("call me"), &mut violations);
+        check_content(&LANGUAGES, Some("l1"), Some("zh-Hant-TW"), &mut violations);
+        check_content(&LANGUAGES, Some("l2"), Some("Klingon!"), &mut violations);
+        check_content(&TIMEZONE, None, Some("Europe/Berlin"), &mut violations);
+        check_content(&TIMEZONE, None, Some("Mars/Olympus_Mons"), &mut violations);
+        check_content(
+            &DESCRIPTION,
+            None,
+            Some(&"x".repeat(2_001)),
+            &mut violations,
+        );
+        check_content(&CREATED, None, Some(&"x".repeat(5_000)), &mut violations);
+        assert_eq!(
+            violations,
+            vec![
+                ContentViolation::new("uris.EA#WEB#2", "URL scheme not allowed"),
+                ContentViolation::new("phone_numbers.p2", "not a valid phone number"),
+                ContentViolation::new("languages.l2", "not a BCP 47 language tag"),
+                ContentViolation::new("timezone", "not an IANA time zone"),
+                ContentViolation::new("description", "too long"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_strips_control_characters() -> Result<(), Error> {
+        let (secret_store, outbound, fossil_settings) = setup()?;
+        let mut p = Profile::default();
+        let update = InputProfile {
+            fun_title: Some(StringWithDisplay {
+                value: Some(String::from("Pope\u{0}\n")),
+                display: None,
+            }),
+            description: Some(StringWithDisplay {
+                value: Some(String::from("line\nbreak\u{7}")),
+                display: None,
+            }),
+            ..InputProfile::default()
+        };
+        update
+            .update_profile(
+                &mut p,
+                &Trust::Staff,
+                &secret_store,
+                &fossil_settings,
+                &outbound,
+            )
+            .await?;
+        assert_eq!(p.fun_title.value.as_deref(), Some("Pope"));
+        assert_eq!(p.description.value.as_deref(), Some("line\nbreak"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_only_editable_values_can_be_changed() {
+        let p = Profile::default();
+        let forged = || {
+            Some(StringWithDisplay {
+                value: Some(String::from("forged")),
+                display: None,
+            })
+        };
+        let forged_values = || {
+            Some(KeyValuesWithDisplay {
+                values: Some(vec![KeyValueInput {
+                    k: String::from("forged"),
+                    v: None,
+                }]),
+                display: None,
+            })
+        };
+        let update = InputProfile {
+            alternative_name: forged(),
+            created: forged(),
+            custom_1_primary_email: forged(),
+            custom_2_primary_email: forged(),
+            description: forged(),
+            first_name: forged(),
+            fun_title: forged(),
+            last_modified: forged(),
+            last_name: forged(),
+            location: forged(),
+            login_method: forged(),
+            picture: forged(),
+            primary_username: forged(),
+            pronouns: forged(),
+            timezone: forged(),
+            user_id: forged(),
+            languages: forged_values(),
+            phone_numbers: forged_values(),
+            tags: forged_values(),
+            uris: forged_values(),
+            usernames: forged_values(),
+            ..InputProfile::default()
+        };
+        let expected: Vec<&str> = update
+            .strings(&p)
+            .iter()
+            .map(|(rule, ..)| *rule)
+            .chain(update.key_values(&p).iter().map(|(rule, ..)| *rule))
+            .filter(|rule| rule.editable != Editable::ValueAndDisplay)
+            .map(|rule| rule.field)
+            .collect();
+        match update.validate(&p, &Trust::Staff) {
+            Err(UpdateError::Invalid { not_editable, .. }) => assert_eq!(not_editable, expected),
+            r => panic!("writes not rejected: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_read_only_values_are_rejected() {
+        let p = Profile::default();
+        let forged = || {
+            Some(StringWithDisplay {
+                value: Some(String::from("forged")),
+                display: None,
+            })
+        };
+        let updates = vec![
+            (
+                "created",
+                InputProfile {
+                    created: forged(),
+                    ..InputProfile::default()
+                },
+            ),
+            (
+                "last_modified",
+                InputProfile {
+                    last_modified: forged(),
+                    ..InputProfile::default()
+                },
+            ),
+            (
+                "login_method",
+                InputProfile {
+                    login_method: forged(),
+                    ..InputProfile::default()
+                },
+            ),
+            (
+                "user_id",
+                InputProfile {
+                    user_id: forged(),
+                    ..InputProfile::default()
+                },
+            ),
+        ];
+        for (field, update) in updates {
+            match update.validate(&p, &Trust::Staff) {
+                Err(UpdateError::Invalid { not_editable, .. }) => {
+                    assert_eq!(not_editable, vec![field])
+                }
+                r => panic!("write to {} not rejected: {:?}", field, r),
+            }
+        }
+    }
+
+    #[test]
+    fn test_all_violations_are_reported_together() {
+        let p = Profile::default();
+        let update = InputProfile {
+            created: Some(StringWithDisplay {
+                value: Some(String::from("forged")),
+                display: None,
+            }),
+            fun_title: Some(StringWithDisplay {
+                value: None,
+                display: Some(Display::Private),
+            }),
+            timezone: Some(StringWithDisplay {
+                value: Some(String::from("Mars/Olympus_Mons")),
+                display: None,
+            }),
+            ..InputProfile::default()
+        };
+        match update.validate(&p, &Trust::Staff) {
+            Err(UpdateError::Invalid {
+                display,
+                content,
+                not_editable,
+            }) => {
+                assert_eq!(
+                    display,
+                    vec![DisplayViolation::new(
+                        "fun_title",
+                        &Display::Private,
+                        DISPLAY_NOT_PRIVATE
+                    )]
+                );
+                assert_eq!(
+                    content,
+                    vec![ContentViolation::new("timezone", "not an IANA time zone")]
+                );
+                assert_eq!(not_editable, vec!["created"]);
+            }
+            r => panic!("update not rejected: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_unchanged_invalid_values_do_not_block_updates() {
+        let mut p = Profile::default();
+        p.timezone.value = Some(String::from("Mars/Olympus_Mons"));
+        p.description.value = Some("x".repeat(5_000));
+        let mut uris = BTreeMap::new();
+        uris.insert(
+            String::from("EA#WEB#1"),
+            Some(String::from("ftp://mozilla.org")),
+        );
+        p.uris.values = Some(KeyValue(uris));
+        let update = InputProfile {
+            timezone: Some(StringWithDisplay {
+                value: p.timezone.value.clone(),
+                display: None,
+            }),
+            description: Some(StringWithDisplay {
+                value: p.description.value.clone(),
+                display: None,
+            }),
+            uris: Some(KeyValuesWithDisplay {
+                values: Some(vec![
+                    KeyValueInput {
+                        k: String::from("EA#WEB#1"),
+                        v: Some(String::from("ftp://mozilla.org")),
+                    },
+                    KeyValueInput {
+                        k: String::from("EA#WEB#2"),
+                        v: Some(String::from("gopher://mozilla.org")),
+                    },
+                ]),
+                display: None,
+            }),
+            fun_title: Some(StringWithDisplay {
+                value: Some(String::from("Pope")),
+                display: None,
+            }),
+            ..InputProfile::default()
+        };
+        match update.validate(&p, &Trust::Staff) {
+            Err(UpdateError::Invalid {
+                display,
+                content,
+                not_editable,
+            }) => {
+                assert!(display.is_empty() && not_editable.is_empty());
+                assert_eq!(
+                    content,
+                    vec![ContentViolation::new(
+                        "uris.EA#WEB#2",
+                        "URL scheme not allowed"
+                    )]
+                );
+            }
+            r => panic!("new uri not rejected: {:?}", r),
+        }
+        let update = InputProfile {
+            uris: None,
+            ..update
+        };
+        assert!(update.validate(&p, &Trust::Staff).is_ok());
+    }
+
+    #[test]
+    fn test_content_is_checked_after_cleaning() {
+        let p = Profile::default();
+        let update = InputProfile {
+            pronouns: Some(StringWithDisplay {
+                value: Some(format!("{}\u{7}", "x".repeat(64))),
+                display: None,
+            }),
+            ..InputProfile::default()
+        };
+        assert!(update.validate(&p, &Trust::Staff).is_ok());
+    }
+
+    #[test]
+    fn test_read_only_display_and_unchanged_value_pass() {
+        let mut p = Profile::default();
+        p.user_id.value = Some(String::from("user1"));
+        let update = InputProfile {
+            user_id: Some(StringWithDisplay {
+                value: Some(String::from("user1")),
+                display: Some(Display::Staff),
+            }),
+            created: Some(StringWithDisplay {
+                value: None,
+                display: Some(Display::Staff),
+            }),
+            // ignored instead of rejected
+            active: Some(BoolWithDisplay {
+                value: Some(!p.active.value.unwrap_or_default()),
+                display: Some(Display::Public),
+            }),
+            ..InputProfile::default()
+        };
+        assert!(update.validate(&p, &Trust::Staff).is_ok());
+    }
+
+    #[test]
+    fn test_change_report_deduplicates() {
+        let mut report = ChangeReport::default();
+        report.push("access_information.mozilliansorg", ChangeKind::Display);
+        report.push("access_information.mozilliansorg", ChangeKind::Display);
+        report.push("access_information.mozilliansorg", ChangeKind::Value);
+        assert_eq!(report.changes.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_update_display_only_with_null_value_string() -> Result<(), Error> {
+        let (secret_store, outbound, fossil_settings) = setup()?;
         let mut p = Profile::default();
         let update = InputProfile {
             fun_title: Some(StringWithDisplay {
@@ -883,7 +2088,13 @@ mod test {
         assert_eq!(p.fun_title.value, None);
         assert_ne!(p.fun_title.metadata.display, Some(Display::Vouched));
         update
-            .update_profile(&mut p, &Trust::Staff, &secret_store, &fossil_settings)
+            .update_profile(
+                &mut p,
+                &Trust::Staff,
+                &secret_store,
+                &fossil_settings,
+                &outbound,
+            )
             .await?;
         assert_eq!(p.pronouns.value, None);
         assert_eq!(p.fun_title.value, Some(String::default()));
@@ -893,10 +2104,7 @@ mod test {
 
     #[tokio::test]
     async fn test_update_display_only_with_null_value_kv() -> Result<(), Error> {
-        let secret_store = get_fake_secret_store();
-        let fossil_settings = Fossil {
-            upload_endpoint: String::default(),
-        };
+        let (secret_store, outbound, fossil_settings) = setup()?;
         let mut p = Profile::default();
         let update = InputProfile {
             languages: Some(KeyValuesWithDisplay {
@@ -909,7 +2117,13 @@ mod test {
         assert_eq!(p.languages.values, None);
         assert_ne!(p.languages.metadata.display, Some(Display::Vouched));
         update
-            .update_profile(&mut p, &Trust::Staff, &secret_store, &fossil_settings)
+            .update_profile(
+                &mut p,
+                &Trust::Staff,
+                &secret_store,
+                &fossil_settings,
+                &outbound,
+            )
             .await?;
         assert_eq!(p.tags.values, None);
         assert_eq!(p.languages.values, Some(Default::default()));
@@ -918,11 +2132,182 @@ mod test {
     }
 
     #[tokio::test]
-    async fn test_update_access_information_display_initializes_groups() -> Result<(), Error> {
-        let secret_store = get_fake_secret_store();
-        let fossil_settings = Fossil {
-            upload_endpoint: String::default(),
+    async fn test_update_reports_changed_fields() -> Result<(), Error> {
+        let (secret_store, outbound, fossil_settings) = setup()?;
+        let mut p = Profile::default();
+        p.tags.values = Some(KeyValue(BTreeMap::default()));
+        let update = InputProfile {
+            fun_title: Some(StringWithDisplay {
+                value: Some(String::from("Pope")),
+                display: Some(Display::Vouched),
+            }),
+            tags: Some(KeyValuesWithDisplay {
+                values: Some(vec![]),
+                display: None,
+            }),
+            staff_information_title_display: Some(Display::Staff),
+            ..Default::default()
+        };
+        let report = update
+            .update_profile(
+                &mut p,
+                &Trust::Staff,
+                &secret_store,
+                &fossil_settings,
+                &outbound,
+            )
+            .await?;
+        assert_eq!(
+            report.changes,
+            vec![
+                FieldChange {
+                    field: String::from("fun_title"),
+                    kind: ChangeKind::Value,
+                },
+                FieldChange {
+                    field: String::from("fun_title"),
+                    kind: ChangeKind::Display,
+                },
+                FieldChange {
+                    field: String::from("tags"),
+                    kind: ChangeKind::Signed,
+                },
+            ]
+        );
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].starts_with("staff_information.title"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remove_identity_is_reported() -> Result<(), Error> {
+        let (secret_store, outbound, fossil_settings) = setup()?;
+        let mut p = Profile::default();
+        let update = InputProfile {
+            identities: Some(IdentitiesWithDisplay {
+                google: Some(IdentityWithDisplay {
+                    remove: Some(true),
+                    display: None,
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let report = update
+            .update_profile(
+                &mut p,
+                &Trust::Staff,
+                &secret_store,
+                &fossil_settings,
+                &outbound,
+            )
+            .await?;
+        assert_eq!(report.changes.len(), 2);
+        assert!(report
+            .changes
+            .iter()
+            .all(|c| c.kind == ChangeKind::RemovedIdentity));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_preview_picture_does_not_call_fossil() -> Result<(), Error> {
+        let (secret_store, outbound, fossil_settings) = setup()?;
+        let mut p = Profile::default();
+        p.uuid.value = Some(String::from("uuid"));
+        p.picture.metadata.display = Some(Display::Staff);
+        let update = InputProfile {
+            picture: Some(StringWithDisplay {
+                value: Some(String::from("intermediate:abc")),
+                display: None,
+            }),
+            ..Default::default()
         };
+        let report = update
+            .preview_profile(
+                &mut p,
+                &Trust::Staff,
+                &secret_store,
+                &fossil_settings,
+                &outbound,
+            )
+            .await?;
+        assert!(report.changed());
+        assert_eq!(p.picture.value, Some(String::from("intermediate:abc")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_clear_picture_defers_deletion() -> Result<(), Error> {
+        let (secret_store, outbound, fossil_settings) = setup()?;
+        let mut p = Profile::default();
+        p.uuid.value = Some(String::from("uuid"));
+        p.picture.value = Some(String::from("/avatar/uuid/picture.png"));
+        p.picture.metadata.display = Some(Display::Staff);
+        let update = InputProfile {
+            picture: Some(StringWithDisplay {
+                value: Some(String::default()),
+                display: None,
+            }),
+            ..Default::default()
+        };
+        let report = update
+            .preview_profile(
+                &mut p.clone(),
+                &Trust::Staff,
+                &secret_store,
+                &fossil_settings,
+                &outbound,
+            )
+            .await?;
+        assert!(report.changed());
+        assert_eq!(report.delete_picture, None);
+        let report = update
+            .update_profile(
+                &mut p,
+                &Trust::Staff,
+                &secret_store,
+                &fossil_settings,
+                &outbound,
+            )
+            .await?;
+        assert_eq!(p.picture.value, Some(String::default()));
+        assert_eq!(report.delete_picture, Some(String::from("uuid")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_clear_picture_with_display_change() -> Result<(), Error> {
+        let (secret_store, outbound, fossil_settings) = setup()?;
+        let mut p = Profile::default();
+        p.uuid.value = Some(String::from("uuid"));
+        p.picture.value = Some(String::from("/avatar/uuid/picture.png"));
+        p.picture.metadata.display = Some(Display::Staff);
+        let update = InputProfile {
+            picture: Some(StringWithDisplay {
+                value: Some(String::default()),
+                display: Some(Display::Public),
+            }),
+            ..Default::default()
+        };
+        let report = update
+            .update_profile(
+                &mut p,
+                &Trust::Staff,
+                &secret_store,
+                &fossil_settings,
+                &outbound,
+            )
+            .await?;
+        assert_eq!(p.picture.value, Some(String::default()));
+        assert_eq!(p.picture.metadata.display, Some(Display::Public));
+        assert_eq!(report.delete_picture, Some(String::from("uuid")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_access_information_display_initializes_groups() -> Result<(), Error> {
+        let (secret_store, outbound, fossil_settings) = setup()?;
         let mut p = Profile::default();
         let update = InputProfile {
             access_information_mozilliansorg_display: Some(Display::Ndaed),
@@ -934,7 +2319,13 @@ mod test {
             Some(Display::Ndaed)
         );
         update
-            .update_profile(&mut p, &Trust::Staff, &secret_store, &fossil_settings)
+            .update_profile(
+                &mut p,
+                &Trust::Staff,
+                &secret_store,
+                &fossil_settings,
+                &outbound,
+            )
             .await?;
         assert_eq!(
             p.access_information.mozilliansorg.values,
@@ -949,10 +2340,7 @@ mod test {
 
     #[tokio::test]
     async fn test_update_access_information_display_keeps_groups() -> Result<(), Error> {
-        let secret_store = get_fake_secret_store();
-        let fossil_settings = Fossil {
-            upload_endpoint: String::default(),
-        };
+        let (secret_store, outbound, fossil_settings) = setup()?;
 
         let mut groups = BTreeMap::new();
         groups.insert(String::from("Something"), None);
@@ -966,7 +2354,13 @@ mod test {
             ..Default::default()
         };
         update
-            .update_profile(&mut p, &Trust::Staff, &secret_store, &fossil_settings)
+            .update_profile(
+                &mut p,
+                &Trust::Staff,
+                &secret_store,
+                &fossil_settings,
+                &outbound,
+            )
             .await?;
         assert_eq!(
             p.access_information.mozilliansorg.values,
@@ -0,0 +1,609 @@
+use crate::graphql_api::error::QueryLimitError;
+use crate::graphql_api::root::MAX_PROFILES_BATCH;
+use crate::settings::GraphQl;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+/// Hard limit for nested selection sets and fragment spreads to keep the
+/// analysis itself from overflowing the stack.
+const MAX_NESTING: usize = 128;
+
+/// Fields resolving one item per element of a list argument, their cost is
+/// multiplied by its length.
+const LIST_ARGUMENTS: &[(&str, &str)] =
+    &[("profiles", "usernames"), ("profilesUpdated", "usernames")];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QueryCost {
+    pub depth: usize,
+    /// Number of selected fields with fragments expanded, fields taking a
+    /// list argument count once per element.
+    pub complexity: usize,
+    pub aliases: usize,
+}
+
+impl QueryCost {
+    fn add(&mut self, other: QueryCost) {
+        self.depth = self.depth.max(other.depth);
+        self.complexity = self.complexity.saturating_add(other.complexity);
+        self.aliases = self.aliases.saturating_add(other.aliases);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Token<'a> {
+    Punct(char),
+    Spread,
+    Name(&'a str),
+    Value,
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token<'_>>, QueryLimitError> {
+    let bytes = query.as_bytes();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b' ' | b'\t' | b'\n' | b'\r' | b',' => i += 1,
+            // a byte order mark is ignored like whitespace
+            0xEF if bytes[i..].starts_with("\u{feff}".as_bytes()) => i += 3,
+            b'#' => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'"' if bytes[i..].starts_with(b"\"\"\"") => {
+                i += 3;
+                loop {
+                    if i >= bytes.len() {
+                        return Err(QueryLimitError::Invalid);
+                    }
+                    if bytes[i..].starts_with(b"\\\"\"\"") {
+                        i += 4;
+                    } else if bytes[i..].starts_with(b"\"\"\"") {
+                        i += 3;
+                        break;
+                    } else {
+                        i += 1;
+                    }
+                }
+                tokens.push(Token::Value);
+            }
+            b'"' => {
+                i += 1;
+                loop {
+                    match bytes.get(i) {
+                        None | Some(b'\n') => return Err(QueryLimitError::Invalid),
+                        Some(b'\\') => i += 2,
+                        Some(b'"') => {
+                            i += 1;
+                            break;
+                        }
+                        Some(_) => i += 1,
+                    }
+                }
+                tokens.push(Token::Value);
+            }
+            b'.' if bytes[i..].starts_with(b"...") => {
+                i += 3;
+                tokens.push(Token::Spread);
+            }
+            c @ (b'!' | b'$' | b'&' | b'(' | b')' | b':' | b'=' | b'@' | b'[' | b']' | b'{'
+            | b'}' | b'|') => {
+                i += 1;
+                tokens.push(Token::Punct(char::from(c)));
+            }
+            c if c == b'_' || c.is_ascii_alphabetic() => {
+                let start = i;
+                while i < bytes.len() && (bytes[i] == b'_' || bytes[i].is_ascii_alphanumeric()) {
+                    i += 1;
+                }
+                tokens.push(Token::Name(&query[start..i]));
+            }
+            c if c == b'-' || c.is_ascii_digit() => {
+                i += 1;
+                while i < bytes.len()
+                    && (bytes[i].is_ascii_alphanumeric() || matches!(bytes[i], b'.' | b'+' | b'-'))
+                {
+                    i += 1;
+                }
+                tokens.push(Token::Value);
+            }
+            _ => return Err(QueryLimitError::Invalid),
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Argument {
+    List(usize),
+    Variable,
+    Other,
+}
+
+enum Selection<'a> {
+    Field {
+        name: &'a str,
+        alias: bool,
+        arguments: Vec<(&'a str, Argument)>,
+        selections: Vec<Selection<'a>>,
+    },
+    Spread(&'a str),
+    Inline(Vec<Selection<'a>>),
+}
+
+#[derive(Default)]
+struct Document<'a> {
+    operations: Vec<Vec<Selection<'a>>>,
+    fragments: HashMap<&'a str, Vec<Selection<'a>>>,
+}
+
+/// Parses just enough of a GraphQL document to measure it. Variables and
+/// directives are skipped, of arguments only list lengths are kept.
+/// Validation is left to juniper.
+struct Parser<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Result<Token<'a>, QueryLimitError> {
+        let token = self.peek().ok_or(QueryLimitError::Invalid)?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, token: Token<'a>) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn name(&mut self) -> Result<&'a str, QueryLimitError> {
+        match self.next()? {
+            Token::Name(name) => Ok(name),
+            _ => Err(QueryLimitError::Invalid),
+        }
+    }
+
+    /// Skips a parenthesized list, e.g. arguments or variable definitions.
+    fn skip_parens(&mut self) -> Result<(), QueryLimitError> {
+        if !self.eat(Token::Punct('(')) {
+            return Ok(());
+        }
+        let mut open = 1;
+        while open > 0 {
+            match self.next()? {
+                Token::Punct('(') => open += 1,
+                Token::Punct(')') => open -= 1,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn skip_value(&mut self) -> Result<(), QueryLimitError> {
+        match self.next()? {
+            Token::Value | Token::Name(_) => {}
+            Token::Punct('$') => {
+                self.name()?;
+            }
+            Token::Punct('[') => {
+                while !self.eat(Token::Punct(']')) {
+                    self.skip_value()?;
+                }
+            }
+            Token::Punct('{') => {
+                while !self.eat(Token::Punct('}')) {
+                    self.name()?;
+                    if !self.eat(Token::Punct(':')) {
+                        return Err(QueryLimitError::Invalid);
+                    }
+                    self.skip_value()?;
+                }
+            }
+            _ => return Err(QueryLimitError::Invalid),
+        }
+        Ok(())
+    }
+
+    fn argument(&mut self) -> Result<Argument, QueryLimitError> {
+        match self.peek() {
+            Some(Token::Punct('$')) => {
+                self.skip_value()?;
+                Ok(Argument::Variable)
+            }
+            Some(Token::Punct('[')) => {
+                self.pos += 1;
+                let mut len = 0;
+                while !self.eat(Token::Punct(']')) {
+                    self.skip_value()?;
+                    len += 1;
+                }
+                Ok(Argument::List(len))
+            }
+            _ => {
+                self.skip_value()?;
+                Ok(Argument::Other)
+            }
+        }
+    }
+
+    fn arguments(&mut self) -> Result<Vec<(&'a str, Argument)>, QueryLimitError> {
+        let mut arguments = vec![];
+        if !self.eat(Token::Punct('(')) {
+            return Ok(arguments);
+        }
+        while !self.eat(Token::Punct(')')) {
+            let name = self.name()?;
+            if !self.eat(Token::Punct(':')) {
+                return Err(QueryLimitError::Invalid);
+            }
+            arguments.push((name, self.argument()?));
+        }
+        Ok(arguments)
+    }
+
+    fn skip_directives(&mut self) -> Result<(), QueryLimitError> {
+        while self.eat(Token::Punct('@')) {
+            self.name()?;
+            self.skip_parens()?;
+        }
+        Ok(())
+    }
+
+    fn document(&mut self) -> Result<Document<'a>, QueryLimitError> {
+        let mut document = Document::default();
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Punct('{') => document.operations.push(self.selection_set(0)?),
+                Token::Name("query" | "mutation" | "subscription") => {
+                    self.pos += 1;
+                    if let Some(Token::Name(_)) = self.peek() {
+                        self.pos += 1;
+                    }
+                    self.skip_parens()?;
+                    self.skip_directives()?;
+                    document.operations.push(self.selection_set(0)?);
+                }
+                Token::Name("fragment") => {
+                    self.pos += 1;
+                    let name = self.name()?;
+                    if self.name()? != "on" {
+                        return Err(QueryLimitError::Invalid);
+                    }
+                    self.name()?;
+                    self.skip_directives()?;
+                    let selections = self.selection_set(0)?;
+                    document.fragments.insert(name, selections);
+                }
+                _ => return Err(QueryLimitError::Invalid),
+            }
+        }
+        Ok(document)
+    }
+
+    fn selection_set(&mut self, nesting: usize) -> Result<Vec<Selection<'a>>, QueryLimitError> {
+        if nesting > MAX_NESTING {
+            return Err(QueryLimitError::TooDeep(MAX_NESTING));
+        }
+        if !self.eat(Token::Punct('{')) {
+            return Err(QueryLimitError::Invalid);
+        }
+        let mut selections = vec![];
+        while !self.eat(Token::Punct('}')) {
+            if self.eat(Token::Spread) {
+                match self.peek() {
+                    Some(Token::Name(name)) if name != "on" => {
+                        self.pos += 1;
+                        self.skip_directives()?;
+                        selections.push(Selection::Spread(name));
+                    }
+                    _ => {
+                        if self.eat(Token::Name("on")) {
+                            self.name()?;
+                        }
+                        self.skip_directives()?;
+                        selections.push(Selection::Inline(self.selection_set(nesting + 1)?));
+                    }
+                }
+                continue;
+            }
+            let mut name = self.name()?;
+            let alias = self.eat(Token::Punct(':'));
+            if alias {
+                name = self.name()?;
+            }
+            let arguments = self.arguments()?;
+            self.skip_directives()?;
+            let children = if self.peek() == Some(Token::Punct('{')) {
+                self.selection_set(nesting + 1)?
+            } else {
+                vec![]
+            };
+            selections.push(Selection::Field {
+                name,
+                alias,
+                arguments,
+                selections: children,
+            });
+        }
+        Ok(selections)
+    }
+}
+
+/// Number of items a field resolves. For list arguments passed as a
+/// variable the upper bound of the `profiles` query is assumed.
+fn weight(name: &str, arguments: &[(&str, Argument)]) -> usize {
+    arguments
+        .iter()
+        .filter(|(argument, _)| LIST_ARGUMENTS.contains(&(name, argument)))
+        .map(|(_, argument)| match argument {
+            Argument::List(len) => *len,
+            Argument::Variable => MAX_PROFILES_BATCH,
+            Argument::Other => 1,
+        })
+        .max()
+        .unwrap_or(1)
+        .max(1)
+}
+
+struct Measure<'d, 'a> {
+    document: &'d Document<'a>,
+    fragments: HashMap<&'a str, QueryCost>,
+    visiting: HashSet<&'a str>,
+}
+
+impl<'d, 'a> Measure<'d, 'a> {
+    fn selections(
+        &mut self,
+        selections: &'d [Selection<'a>],
+        nesting: usize,
+    ) -> Result<QueryCost, QueryLimitError> {
+        if nesting > MAX_NESTING {
+            return Err(QueryLimitError::TooDeep(MAX_NESTING));
+        }
+        let mut cost = QueryCost::default();
+        for selection in selections {
+            match selection {
+                Selection::Field {
+                    name,
+                    alias,
+                    arguments,
+                    selections,
+                } => {
+                    let children = self.selections(selections, nesting + 1)?;
+                    cost.add(QueryCost {
+                        depth: children.depth + 1,
+                        complexity: children
+                            .complexity
+                            .saturating_add(1)
+                            .saturating_mul(weight(name, arguments)),
+                        aliases: children.aliases + usize::from(*alias),
+                    });
+                }
+                Selection::Spread(name) => cost.add(self.fragment(name, nesting + 1)?),
+                Selection::Inline(selections) => {
+                    cost.add(self.selections(selections, nesting + 1)?)
+                }
+            }
+        }
+        Ok(cost)
+    }
+
+    fn fragment(&mut self, name: &'a str, nesting: usize) -> Result<QueryCost, QueryLimitError> {
+        if let Some(cost) = self.fragments.get(name) {
+            return Ok(*cost);
+        }
+        let selections = self
+            .document
+            .fragments
+            .get(name)
+            .ok_or(QueryLimitError::Invalid)?;
+        // fragment cycles are invalid
+        if !self.visiting.insert(name) {
+            return Err(QueryLimitError::Invalid);
+        }
+        let cost = self.selections(selections, nesting)?;
+        self.visiting.remove(name);
+        self.fragments.insert(name, cost);
+        Ok(cost)
+    }
+}
+
+/// Cost of the most expensive operation in `query`.
+pub fn measure(query: &str) -> Result<QueryCost, QueryLimitError> {
+    let document = Parser {
+        tokens: tokenize(query)?,
+        pos: 0,
+    }
+    .document()?;
+    let mut measure = Measure {
+        document: &document,
+        fragments: HashMap::new(),
+        visiting: HashSet::new(),
+    };
+    let mut cost = QueryCost::default();
+    for operation in &document.operations {
+        let operation = measure.selections(operation, 0)?;
+        cost.depth = cost.depth.max(operation.depth);
+        cost.complexity = cost.complexity.max(operation.complexity);
+        cost.aliases = cost.aliases.max(operation.aliases);
+    }
+    Ok(cost)
+}
+
+/// Checks all queries of a (batch) request. Complexity and aliases are
+/// summed up since the whole batch is executed at once.
+pub fn check_limits<Q: AsRef<str>>(
+    queries: &[Q],
+    limits: &GraphQl,
+) -> Result<QueryCost, QueryLimitError> {
+    if queries.len() > limits.max_batch_size {
+        return Err(QueryLimitError::BatchTooLarge(limits.max_batch_size));
+    }
+    let mut cost = QueryCost::default();
+    for query in queries {
+        cost.add(measure(query.as_ref()).map_err(|e| match e {
+            // hit `MAX_NESTING`, which is way beyond any sane limit
+            QueryLimitError::TooDeep(_) => QueryLimitError::TooDeep(limits.max_depth),
+            e => e,
+        })?);
+    }
+    if cost.depth > limits.max_depth {
+        return Err(QueryLimitError::TooDeep(limits.max_depth));
+    }
+    if cost.complexity > limits.max_complexity {
+        return Err(QueryLimitError::TooComplex(limits.max_complexity));
+    }
+    if cost.aliases > limits.max_aliases {
+        return Err(QueryLimitError::TooManyAliases(limits.max_aliases));
+    }
+    Ok(cost)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_measure_fields_and_depth() -> Result<(), QueryLimitError> {
+        let cost = measure(
+            r#"query Profile($u: String = "x") {
+                profile(username: $u) {
+                    firstName { value }
+                    identities { githubIdV3 { value metadata { display } } }
+                }
+            }"#,
+        )?;
+        assert_eq!(cost.depth, 5);
+        assert_eq!(cost.complexity, 8);
+        assert_eq!(cost.aliases, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_measure_expands_fragments() -> Result<(), QueryLimitError> {
+        let cost = measure(
+            "{ a: profile { ...F } b: profile { ...F ... on Profile { uuid } } }
+             fragment F on Profile { firstName { value } }",
+        )?;
+        assert_eq!(cost.depth, 3);
+        assert_eq!(cost.complexity, 7);
+        assert_eq!(cost.aliases, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_measure_ignores_strings_and_comments() -> Result<(), QueryLimitError> {
+        let cost = measure(
+            r#"# { { {
+            { profile(username: "{ a { b } }", view: """ { "" } """) { uuid } }"#,
+        )?;
+        assert_eq!(cost.depth, 2);
+        assert_eq!(cost.complexity, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_measure_weights_list_arguments() -> Result<(), QueryLimitError> {
+        let cost = measure(
+            r#"{ profiles(usernames: ["a", "b", "c"], viewAs: PUBLIC) { uuid firstName { value } } }"#,
+        )?;
+        assert_eq!(cost.complexity, 12);
+        let cost =
+            measure(r#"query Profiles($u: [String!]!) { profiles(usernames: $u) { uuid } }"#)?;
+        assert_eq!(cost.complexity, 2 * MAX_PROFILES_BATCH);
+        let cost = measure(r#"{ profiles(usernames: "a") { uuid } }"#)?;
+        assert_eq!(cost.complexity, 2);
+        let cost = measure(r#"subscription { profilesUpdated(usernames: ["a", "b"]) { uuid } }"#)?;
+        assert_eq!(cost.complexity, 4);
+        let cost = measure(r#"{ profile(username: "a") { tags(k: ["x", "y"]) } }"#)?;
+        assert_eq!(cost.complexity, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_measure_rejects_invalid() {
+        assert!(matches!(
+            measure("{ a { b }"),
+            Err(QueryLimitError::Invalid)
+        ));
+        assert!(matches!(
+            measure("{ ...A } fragment A on X { ...A }"),
+            Err(QueryLimitError::Invalid)
+        ));
+        assert!(matches!(
+            measure(&format!("{}{}", "{ a ".repeat(1000), "}".repeat(1000))),
+            Err(QueryLimitError::TooDeep(_))
+        ));
+    }
+
+    #[test]
+    fn test_measure_ignores_byte_order_mark() -> Result<(), QueryLimitError> {
+        assert_eq!(measure("\u{feff}{ profile { uuid } }")?.depth, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_limits() {
+        let limits = GraphQl {
+            max_depth: 2,
+            max_complexity: 3,
+            max_aliases: 1,
+            ..Default::default()
+        };
+        assert!(check_limits(&["{ a { b } }"], &limits).is_ok());
+        assert!(matches!(
+            check_limits(&["{ a { b { c } } }"], &limits),
+            Err(QueryLimitError::TooDeep(2))
+        ));
+        assert!(matches!(
+            check_limits(&["{ a b c d }"], &limits),
+            Err(QueryLimitError::TooComplex(3))
+        ));
+        assert!(matches!(
+            check_limits(&["{ x: a y: a }"], &limits),
+            Err(QueryLimitError::TooManyAliases(1))
+        ));
+        assert!(matches!(
+            check_limits(
+                &[format!("{}{}", "{ a ".repeat(1000), "}".repeat(1000))],
+                &limits
+            ),
+            Err(QueryLimitError::TooDeep(2))
+        ));
+    }
+
+    #[test]
+    fn test_check_limits_sums_up_batches() {
+        let limits = GraphQl {
+            max_depth: 2,
+            max_complexity: 3,
+            max_aliases: 1,
+            max_batch_size: 3,
+            ..Default::default()
+        };
+        assert!(check_limits(&["{ a }", "{ b }", "{ c }"], &limits).is_ok());
+        assert!(matches!(
+            check_limits(&["{ a b }", "{ c d }"], &limits),
+            Err(QueryLimitError::TooComplex(3))
+        ));
+        assert!(matches!(
+            check_limits(&["{ x: a }", "{ y: a }"], &limits),
+            Err(QueryLimitError::TooManyAliases(1))
+        ));
+        assert!(matches!(
+            check_limits(&["{ a }"; 4], &limits),
+            Err(QueryLimitError::BatchTooLarge(3))
+        ));
+    }
+}
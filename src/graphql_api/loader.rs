@@ -0,0 +1,174 @@
+use crate::metrics::Metrics;
+use crate::metrics::Upstream;
+use cis_client::error::CisClientError;
+use cis_client::error::ProfileError;
+use cis_client::getby::GetBy;
+use cis_client::AsyncCisClientTrait;
+use cis_profile::schema::Profile;
+use futures::lock::Mutex as AsyncMutex;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct LoadKey {
+    id: String,
+    by: &'static str,
+    filter: Option<String>,
+    any: bool,
+}
+
+/// `None` caches a profile that does not exist.
+type Slot = Arc<AsyncMutex<Option<Option<Profile>>>>;
+
+/// Per request cache for CIS profile lookups. Concurrent lookups of the same
+/// profile wait for the first one instead of hitting CIS again. Only found
+/// and non-existing profiles are cached, other errors are retried.
+#[derive(Default)]
+pub struct Loader {
+    slots: Mutex<HashMap<LoadKey, Slot>>,
+}
+
+impl Loader {
+    pub async fn get_user_by(
+        &self,
+        cis_client: &impl AsyncCisClientTrait,
+        metrics: &Metrics,
+        id: &str,
+        by: &GetBy,
+        filter: Option<&str>,
+    ) -> Result<Profile, CisClientError> {
+        let key = LoadKey {
+            id: id.to_owned(),
+            by: by.as_str(),
+            filter: filter.map(String::from),
+            any: false,
+        };
+        self.load(key, || {
+            metrics.observe_upstream(Upstream::Cis, cis_client.get_user_by(id, by, filter))
+        })
+        .await
+    }
+
+    pub async fn get_any_user_by(
+        &self,
+        cis_client: &impl AsyncCisClientTrait,
+        metrics: &Metrics,
+        id: &str,
+        by: &GetBy,
+        filter: Option<&str>,
+    ) -> Result<Profile, CisClientError> {
+        let key = LoadKey {
+            id: id.to_owned(),
+            by: by.as_str(),
+            filter: filter.map(String::from),
+            any: true,
+        };
+        self.load(key, || {
+            metrics.observe_upstream(Upstream::Cis, cis_client.get_any_user_by(id, by, filter))
+        })
+        .await
+    }
+
+    /// Drops all cached profiles, e.g. after a profile has been updated.
+    pub fn clear(&self) {
+        self.slots.lock().unwrap().clear();
+    }
+
+    async fn load<F, Fut>(&self, key: LoadKey, fetch: F) -> Result<Profile, CisClientError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Profile, CisClientError>>,
+    {
+        let slot = Arc::clone(self.slots.lock().unwrap().entry(key).or_default());
+        let mut slot = slot.lock().await;
+        let loaded = match &*slot {
+            Some(loaded) => loaded.clone(),
+            None => {
+                let loaded = match fetch().await {
+                    Ok(profile) => Some(profile),
+                    Err(CisClientError::ProfileError(ProfileError::ProfileDoesNotExist)) => None,
+                    Err(e) => return Err(e),
+                };
+                *slot = Some(loaded.clone());
+                loaded
+            }
+        };
+        loaded.ok_or(CisClientError::ProfileError(
+            ProfileError::ProfileDoesNotExist,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::future::join;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    fn key(id: &str) -> LoadKey {
+        LoadKey {
+            id: String::from(id),
+            by: GetBy::UserId.as_str(),
+            filter: None,
+            any: false,
+        }
+    }
+
+    async fn fetch(calls: &AtomicUsize, exists: bool) -> Result<Profile, CisClientError> {
+        calls.fetch_add(1, Ordering::SeqCst);
+        if exists {
+            Ok(Profile::default())
+        } else {
+            Err(CisClientError::ProfileError(
+                ProfileError::ProfileDoesNotExist,
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_profile_is_loaded_once() {
+        let loader = Loader::default();
+        let calls = AtomicUsize::new(0);
+        let (a, b) = join(
+            loader.load(key("a"), || fetch(&calls, true)),
+            loader.load(key("a"), || fetch(&calls, true)),
+        )
+        .await;
+        assert!(a.is_ok() && b.is_ok());
+        assert!(loader.load(key("a"), || fetch(&calls, true)).await.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(loader.load(key("b"), || fetch(&calls, true)).await.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_missing_profile_is_cached() {
+        let loader = Loader::default();
+        let calls = AtomicUsize::new(0);
+        for _ in 0..2 {
+            assert!(matches!(
+                loader.load(key("a"), || fetch(&calls, false)).await,
+                Err(CisClientError::ProfileError(
+                    ProfileError::ProfileDoesNotExist
+                ))
+            ));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_clear_reloads() {
+        let loader = Loader::default();
+        let calls = AtomicUsize::new(0);
+        assert!(loader
+            .load(key("a"), || fetch(&calls, false))
+            .await
+            .is_err());
+        loader.clear();
+        assert!(loader.load(key("a"), || fetch(&calls, true)).await.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
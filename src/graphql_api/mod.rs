@@ -1,5 +1,10 @@
 pub mod app;
+mod audit;
 mod avatar;
+pub mod bus;
 mod error;
 mod input;
+mod limits;
+mod loader;
 pub mod root;
+pub mod username;
@@ -1,74 +1,168 @@
-use crate::graphql_api::error::field_error;
+use crate::graphql_api::audit::diff_profiles;
+use crate::graphql_api::audit::record_update;
+use crate::graphql_api::audit::AuditSink;
+use crate::graphql_api::avatar::delete_picture;
+use crate::graphql_api::bus::ProfileBus;
+use crate::graphql_api::bus::ProfileEvent;
+use crate::graphql_api::error::GraphQlError;
+use crate::graphql_api::error::GraphQlResult;
+use crate::graphql_api::input::ChangeReport;
+use crate::graphql_api::input::FieldChange;
 use crate::graphql_api::input::InputProfile;
+use crate::graphql_api::loader::Loader;
+use crate::graphql_api::username::username_status;
+use crate::graphql_api::username::ReservedNames;
+use crate::graphql_api::username::UsernameStatus;
 use crate::metrics::Metrics;
+use crate::metrics::Upstream;
+use crate::orgchart::cache::OrgchartCache;
+use crate::outbound::Outbound;
+use crate::ratelimit::RateLimiter;
 use crate::settings::DinoParkServices;
+use crate::settings::Usernames;
+use crate::usernames::app::resolve;
+use crate::usernames::history::UsernameHistory;
 use cis_client::error::{CisClientError, ProfileError};
 use cis_client::getby::GetBy;
 use cis_client::AsyncCisClientTrait;
 use cis_profile::schema::Display;
 use cis_profile::schema::Profile;
 use dino_park_gate::scope::ScopeAndUser;
+use dino_park_trust::GroupsTrust;
 use dino_park_trust::Trust;
-use juniper::FieldError;
+use futures::future::ready;
+use futures::stream;
+use futures::Stream;
+use futures::StreamExt;
 use juniper::FieldResult;
+use juniper::GraphQLObject;
+use juniper::IntoFieldError;
 use juniper::RootNode;
 use log::error;
 use log::info;
 use log::warn;
-use reqwest::Client;
+use std::pin::Pin;
 use std::sync::Arc;
 
 const INVALID_USERNAME_MESSAGE: &str = "\
 Length of username must be between 2 and 64. \
 And only contain lowercase letters from a-z, digits from 0-9, underscore or hyphen.";
 
+/// Maximum number of usernames in a single `profiles` query.
+pub const MAX_PROFILES_BATCH: usize = 50;
+/// Maximum number of concurrent CIS requests for a `profiles` query.
+const MAX_PROFILES_CONCURRENCY: usize = 10;
+
 pub struct Query<T: AsyncCisClientTrait> {
     pub cis_client: T,
+    pub username_limiter: Arc<RateLimiter>,
+    pub reserved_names: Arc<ReservedNames>,
+    pub username_history: Arc<dyn UsernameHistory>,
+    pub usernames: Usernames,
+}
+
+#[derive(GraphQLObject)]
+pub struct ProfileResult {
+    username: String,
+    profile: Option<Profile>,
+    error: Option<String>,
 }
 
 async fn get_profile(
     id: String,
     cis_client: &impl AsyncCisClientTrait,
+    metrics: &Metrics,
+    loader: &Loader,
     by: &GetBy,
     filter: &str,
 ) -> Result<Profile, CisClientError> {
-    cis_client.get_user_by(&id, by, Some(filter)).await
+    loader
+        .get_user_by(cis_client, metrics, &id, by, Some(filter))
+        .await
 }
 
 pub struct Mutation<T: AsyncCisClientTrait> {
     pub cis_client: T,
     pub dinopark_settings: DinoParkServices,
+    pub audit_sink: Arc<dyn AuditSink>,
+    pub orgchart_cache: Arc<OrgchartCache>,
+    pub outbound: Arc<Outbound>,
+    pub reserved_names: Arc<ReservedNames>,
+    pub username_history: Arc<dyn UsernameHistory>,
+    pub profile_bus: Arc<ProfileBus>,
 }
 
-fn valid_username(username: &str) -> Result<(), FieldError> {
-    let num_chars = username.chars().count();
-    if !(2..=64).contains(&num_chars) {
-        return Err(field_error("username_length", INVALID_USERNAME_MESSAGE));
-    }
-    let only_valid_chars = username
-        .chars()
-        .all(|c| (c.is_ascii_lowercase() || c.is_ascii_digit()) || c == '-' || c == '_');
-    if !only_valid_chars {
-        return Err(field_error(
-            "username_invalid_chars",
-            INVALID_USERNAME_MESSAGE,
-        ));
+fn valid_username(username: &str) -> GraphQlResult<()> {
+    match username_status(username) {
+        UsernameStatus::TooShort | UsernameStatus::TooLong => {
+            Err(GraphQlError::UsernameLength(INVALID_USERNAME_MESSAGE))
+        }
+        UsernameStatus::InvalidChars => {
+            Err(GraphQlError::UsernameInvalidChars(INVALID_USERNAME_MESSAGE))
+        }
+        _ => Ok(()),
     }
-    Ok(())
 }
 
-async fn update_profile(
-    update: InputProfile,
-    cis_client: &impl AsyncCisClientTrait,
-    dinopark_settings: &DinoParkServices,
+/// Previous usernames are kept for their owner during the grace period.
+async fn recently_used(
+    username: &str,
+    user_id: &str,
+    history: &dyn UsernameHistory,
+    settings: &Usernames,
+) -> GraphQlResult<bool> {
+    let grace = chrono::Duration::days(i64::from(settings.history_grace_days));
+    Ok(history
+        .lookup(username)
+        .await
+        .map_err(|e| GraphQlError::Internal("unable_to_check_username", e))?
+        .is_some_and(|previous| previous.blocks(user_id, grace, chrono::Utc::now())))
+}
+
+/// Admins may claim reserved usernames, e.g. for service accounts.
+fn may_claim(username: &str, reserved_names: &ReservedNames, groups_scope: &GroupsTrust) -> bool {
+    matches!(groups_scope, GroupsTrust::Admin) || !reserved_names.is_reserved(username)
+}
+
+#[derive(GraphQLObject)]
+pub struct ProfilePreview {
+    profile: Profile,
+    changed_fields: Vec<String>,
+}
+
+#[derive(GraphQLObject)]
+pub struct ProfileUpdateResult {
+    profile: Profile,
+    changed_fields: Vec<FieldChange>,
+    warnings: Vec<String>,
+}
+
+struct PreparedUpdate {
+    user_id: String,
+    before: Profile,
+    profile: Profile,
+    report: ChangeReport,
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn prepare_update<T: AsyncCisClientTrait>(
+    update: &InputProfile,
+    mutation: &Mutation<T>,
+    metrics: &Metrics,
+    loader: &Loader,
     user: &Option<String>,
-    scope: Trust,
-) -> FieldResult<(Profile, bool)> {
+    scope: &Trust,
+    groups_scope: &GroupsTrust,
+    dry_run: bool,
+) -> GraphQlResult<PreparedUpdate> {
+    let cis_client = &mutation.cis_client;
+    let fossil_settings = &mutation.dinopark_settings.fossil;
+    let outbound = &*mutation.outbound;
     let user_id = user
         .clone()
-        .ok_or_else(|| field_error("no username in query or scope", "?!"))?;
-    let mut profile = cis_client
-        .get_user_by(&user_id, &GetBy::UserId, None)
+        .ok_or(GraphQlError::Forbidden("no username in query or scope"))?;
+    let mut profile = loader
+        .get_user_by(cis_client, metrics, &user_id, &GetBy::UserId, None)
         .await?;
     if let Some(updated_username) = update
         .primary_username
@@ -77,56 +171,225 @@ async fn update_profile(
     {
         if Some(updated_username) != profile.primary_username.value.as_ref() {
             valid_username(updated_username)?;
+            if !may_claim(updated_username, &mutation.reserved_names, groups_scope) {
+                return Err(GraphQlError::UsernameReserved);
+            }
+            if recently_used(
+                updated_username,
+                &user_id,
+                &*mutation.username_history,
+                &mutation.dinopark_settings.usernames,
+            )
+            .await?
+            {
+                return Err(GraphQlError::UsernameTaken);
+            }
             // the primary_username changed check if it already exists
-            if cis_client
-                .get_any_user_by(updated_username, &GetBy::PrimaryUsername, None)
+            if loader
+                .get_any_user_by(
+                    cis_client,
+                    metrics,
+                    updated_username,
+                    &GetBy::PrimaryUsername,
+                    None,
+                )
                 .await
                 .is_ok()
             {
-                return Err(field_error(
-                    "username_exists",
-                    "This username already exists!",
-                ));
+                return Err(GraphQlError::UsernameTaken);
             }
         }
     }
 
-    let changed = update
-        .update_profile(
-            &mut profile,
-            &scope,
-            cis_client.get_secret_store(),
-            &dinopark_settings.fossil,
-        )
-        .await
-        .map_err(|e| field_error("unable update/sign profile", e))?;
-    if changed {
-        let ret = cis_client.update_user(&user_id, profile).await?;
+    let before = profile.clone();
+    let secret_store = cis_client.get_secret_store();
+    let report = if dry_run {
+        update
+            .preview_profile(&mut profile, scope, secret_store, fossil_settings, outbound)
+            .await
+    } else {
+        update
+            .update_profile(&mut profile, scope, secret_store, fossil_settings, outbound)
+            .await
+    }?;
+    Ok(PreparedUpdate {
+        user_id,
+        before,
+        profile,
+        report,
+    })
+}
+
+async fn preview_profile<T: AsyncCisClientTrait>(
+    update: InputProfile,
+    mutation: &Mutation<T>,
+    metrics: &Metrics,
+    loader: &Loader,
+    user: &Option<String>,
+    scope: Trust,
+    groups_scope: &GroupsTrust,
+) -> GraphQlResult<ProfilePreview> {
+    let PreparedUpdate {
+        before, profile, ..
+    } = prepare_update(
+        &update,
+        mutation,
+        metrics,
+        loader,
+        user,
+        &scope,
+        groups_scope,
+        true,
+    )
+    .await?;
+    let changed_fields = diff_profiles(&before, &profile)
+        .map_err(|e| GraphQlError::Internal("unable to diff profile", e))?
+        .into_iter()
+        .map(|diff| diff.field)
+        .collect();
+    Ok(ProfilePreview {
+        profile,
+        changed_fields,
+    })
+}
+
+async fn update_profile<T: AsyncCisClientTrait>(
+    update: InputProfile,
+    mutation: &Mutation<T>,
+    metrics: &Metrics,
+    loader: &Loader,
+    user: &Option<String>,
+    scope: Trust,
+    groups_scope: &GroupsTrust,
+) -> GraphQlResult<(Profile, ChangeReport)> {
+    let Mutation {
+        cis_client,
+        dinopark_settings,
+        audit_sink,
+        orgchart_cache,
+        outbound,
+        username_history,
+        profile_bus,
+        ..
+    } = mutation;
+    let PreparedUpdate {
+        user_id,
+        before,
+        profile,
+        mut report,
+    } = prepare_update(
+        &update,
+        mutation,
+        metrics,
+        loader,
+        user,
+        &scope,
+        groups_scope,
+        false,
+    )
+    .await?;
+    if report.changed() {
+        let ret = metrics
+            .observe_upstream(Upstream::Cis, cis_client.update_user(&user_id, profile))
+            .await?;
         info!("update returned: {}", ret);
-        let updated_profile = cis_client
-            .get_user_by(&user_id, &GetBy::UserId, None)
+        // CIS accepted the update, so the old picture has to go even if
+        // re-fetching the profile below fails
+        if let Some(uuid) = report.delete_picture.clone() {
+            if let Err(e) =
+                delete_picture(&uuid, &dinopark_settings.fossil.upload_endpoint, outbound).await
+            {
+                error!("unable to delete picture for {}: {}", uuid, e);
+                report.warn("picture", "old picture could not be deleted");
+            }
+        }
+        // everything loaded so far is outdated now
+        loader.clear();
+        orgchart_cache.clear();
+        let updated_profile = loader
+            .get_user_by(cis_client, metrics, &user_id, &GetBy::UserId, None)
             .await?;
+        if let (Some(old), Some(new)) = (
+            before.primary_username.value.as_ref(),
+            updated_profile.primary_username.value.as_ref(),
+        ) {
+            if old != new {
+                if let Err(e) = username_history.record(&user_id, old, new).await {
+                    error!("unable to record username change for {}: {}", user_id, e);
+                }
+            }
+        }
+        let event = ProfileEvent {
+            user_id: user_id.clone(),
+            username: updated_profile.primary_username.value.clone(),
+            previous_username: before
+                .primary_username
+                .value
+                .clone()
+                .filter(|old| Some(old) != updated_profile.primary_username.value.as_ref()),
+        };
+        if let Err(e) = profile_bus.publish(event).await {
+            error!("unable to publish profile update for {}: {}", user_id, e);
+        }
+        if let Err(e) =
+            record_update(&**audit_sink, &user_id, &scope, &before, &updated_profile).await
+        {
+            error!("unable to audit profile update for {}: {}", user_id, e);
+        }
         if dinopark_settings.lookout.internal_update_enabled {
-            if let Err(e) = Client::new()
-                .post(&dinopark_settings.lookout.internal_update_endpoint)
-                .json(&updated_profile)
-                .send()
+            if let Err(e) = outbound
+                .send(
+                    Upstream::Lookout,
+                    outbound
+                        .post(&dinopark_settings.lookout.internal_update_endpoint)
+                        .json(&updated_profile),
+                )
                 .await
             {
                 error!("unable to post to lookout: {}", e);
             }
         }
-        Ok((updated_profile, changed))
+        Ok((updated_profile, report))
     } else {
-        Ok((profile, changed))
+        Ok((profile, report))
+    }
+}
+
+fn count_changes(metrics: &Metrics, report: &ChangeReport) {
+    if !report.changed() {
+        return;
+    }
+    metrics.counters.field_any_changed.inc();
+    for change in &report.changes {
+        metrics
+            .counters
+            .field_changed
+            .with_label_values(&[&change.field, change.kind.as_str()])
+            .inc();
+    }
+}
+
+fn count_rejection(metrics: &Metrics, e: &GraphQlError) {
+    match e {
+        GraphQlError::InvalidUpdate { .. }
+        | GraphQlError::UsernameTaken
+        | GraphQlError::UsernameReserved
+        | GraphQlError::UsernameLength(_)
+        | GraphQlError::UsernameInvalidChars(_)
+        | GraphQlError::InvalidViewAs => metrics
+            .counters
+            .update_rejected
+            .with_label_values(&[e.message()])
+            .inc(),
+        _ => {}
     }
 }
 
 #[juniper::graphql_object{
-    Context = (ScopeAndUser, Arc<Metrics>)
+    Context = (ScopeAndUser, Arc<Metrics>, Arc<Loader>)
 }]
 impl<T: AsyncCisClientTrait + Send + Sync> Query<T> {
-    async fn profile(username: Option<String>, view_as: Option<Display>) -> FieldResult<Profile> {
+    async fn profile(username: Option<String>, view_as: Option<Display>) -> GraphQlResult<Profile> {
         let self_query = username.is_none();
         let executor = &executor;
         let scope_and_user = &executor.context().0;
@@ -134,11 +397,16 @@ impl<T: AsyncCisClientTrait + Send + Sync> Query<T> {
             return Ok(Profile::default());
         }
 
-        let params = get_profile_params(username, scope_and_user, view_as)?;
+        let params = get_profile_params(username, scope_and_user, view_as).map_err(|e| {
+            count_rejection(&executor.context().1, &e);
+            e
+        })?;
 
         match get_profile(
             params.id,
             &self.cis_client,
+            &executor.context().1,
+            &executor.context().2,
             &params.by,
             params.filter.as_str(),
         )
@@ -146,51 +414,313 @@ impl<T: AsyncCisClientTrait + Send + Sync> Query<T> {
         {
             Ok(p) => Ok(p),
             Err(CisClientError::ProfileError(ProfileError::ProfileDoesNotExist)) if self_query => {
-                Err(FieldError::new(
-                    "wait_for_profile",
-                    graphql_value!({"warning": "profile does not exist yet"}),
-                ))
+                Err(GraphQlError::ProfileNotReady)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn profiles(
+        usernames: Vec<String>,
+        view_as: Option<Display>,
+    ) -> GraphQlResult<Vec<ProfileResult>> {
+        let executor = &executor;
+        let (scope_and_user, metrics, loader) = executor.context();
+        if usernames.len() > MAX_PROFILES_BATCH {
+            return Err(GraphQlError::BatchTooLarge(MAX_PROFILES_BATCH));
+        }
+        let results = stream::iter(usernames)
+            .map(|username| {
+                get_profile_result(
+                    username,
+                    &self.cis_client,
+                    metrics,
+                    loader,
+                    scope_and_user,
+                    view_as.clone(),
+                )
+            })
+            .buffered(MAX_PROFILES_CONCURRENCY)
+            .collect()
+            .await;
+        Ok(results)
+    }
+
+    /// The current username for a previous one, `null` if `username` was
+    /// never changed or for public requests.
+    async fn resolve_username(username: String) -> GraphQlResult<Option<String>> {
+        let executor = &executor;
+        if executor.context().0.scope == Trust::Public {
+            return Ok(None);
+        }
+        resolve(&*self.username_history, &username)
+            .await
+            .map_err(|e| GraphQlError::Internal("unable_to_resolve_username", e.into()))
+    }
+
+    async fn username_available(username: String) -> GraphQlResult<UsernameStatus> {
+        let executor = &executor;
+        let (scope_and_user, metrics, loader) = executor.context();
+        if scope_and_user.scope == Trust::Public {
+            return Err(GraphQlError::Forbidden("username_available not allowed"));
+        }
+        if let Err(retry_after) = self.username_limiter.check(&scope_and_user.user_id) {
+            let retry_after =
+                i32::try_from(retry_after.as_secs().saturating_add(1)).unwrap_or(i32::MAX);
+            return Err(GraphQlError::RateLimited(retry_after));
+        }
+        let status = username_status(&username);
+        if status != UsernameStatus::Ok {
+            return Ok(status);
+        }
+        if !may_claim(
+            &username,
+            &self.reserved_names,
+            &scope_and_user.groups_scope,
+        ) {
+            return Ok(UsernameStatus::Reserved);
+        }
+        if recently_used(
+            &username,
+            &scope_and_user.user_id,
+            &*self.username_history,
+            &self.usernames,
+        )
+        .await?
+        {
+            return Ok(UsernameStatus::Taken);
+        }
+        match loader
+            .get_any_user_by(
+                &self.cis_client,
+                metrics,
+                &username,
+                &GetBy::PrimaryUsername,
+                None,
+            )
+            .await
+        {
+            // keeping the current username is always fine
+            Ok(profile) if profile.user_id.value.as_ref() == Some(&scope_and_user.user_id) => {
+                Ok(UsernameStatus::Ok)
+            }
+            Ok(_) => Ok(UsernameStatus::Taken),
+            Err(CisClientError::ProfileError(ProfileError::ProfileDoesNotExist)) => {
+                Ok(UsernameStatus::Ok)
             }
             Err(e) => Err(e.into()),
         }
     }
 }
 
+async fn get_profile_result(
+    username: String,
+    cis_client: &impl AsyncCisClientTrait,
+    metrics: &Metrics,
+    loader: &Loader,
+    scope_and_user: &ScopeAndUser,
+    view_as: Option<Display>,
+) -> ProfileResult {
+    let result = match get_profile_params(Some(username.clone()), scope_and_user, view_as) {
+        Ok(params) => get_profile(
+            params.id,
+            cis_client,
+            metrics,
+            loader,
+            &params.by,
+            params.filter.as_str(),
+        )
+        .await
+        .map_err(|e| match e {
+            CisClientError::ProfileError(ProfileError::ProfileDoesNotExist) => {
+                String::from("profile_does_not_exist")
+            }
+            e => {
+                error!("unable to retrieve profile {}: {}", username, e);
+                String::from("unable_to_retrieve_profile")
+            }
+        }),
+        Err(e) => Err(e.message().to_owned()),
+    };
+    match result {
+        Ok(profile) => ProfileResult {
+            username,
+            profile: Some(profile),
+            error: None,
+        },
+        Err(error) => ProfileResult {
+            username,
+            profile: None,
+            error: Some(error),
+        },
+    }
+}
+
 #[juniper::graphql_object{
-    Context = (ScopeAndUser, Arc<Metrics>)
+    Context = (ScopeAndUser, Arc<Metrics>, Arc<Loader>)
 }]
 impl<T: AsyncCisClientTrait + Send + Sync> Mutation<T> {
-    async fn profile(update: InputProfile) -> FieldResult<Profile> {
+    async fn profile(update: InputProfile) -> GraphQlResult<Profile> {
         let executor = &executor;
         let scope_and_user = &executor.context().0;
         if scope_and_user.scope == Trust::Public {
             return Ok(Profile::default());
         }
-        match update_profile(
+        let (profile, report) = update_profile(
             update,
-            &self.cis_client,
-            &self.dinopark_settings,
+            self,
+            &executor.context().1,
+            &executor.context().2,
             &Some(executor.context().0.user_id.clone()),
             executor.context().0.scope.clone(),
+            &executor.context().0.groups_scope,
         )
         .await
-        {
-            Ok((profile, true)) => {
-                executor.context().1.counters.field_any_changed.inc();
-                Ok(profile)
-            }
-            Ok((profile, false)) => Ok(profile),
-            Err(e) => Err(e),
+        .map_err(|e| {
+            count_rejection(&executor.context().1, &e);
+            e
+        })?;
+        count_changes(&executor.context().1, &report);
+        Ok(profile)
+    }
+
+    async fn update_profile(update: InputProfile) -> GraphQlResult<ProfileUpdateResult> {
+        let executor = &executor;
+        let scope_and_user = &executor.context().0;
+        if scope_and_user.scope == Trust::Public {
+            return Ok(ProfileUpdateResult {
+                profile: Profile::default(),
+                changed_fields: vec![],
+                warnings: vec![],
+            });
+        }
+        let (profile, report) = update_profile(
+            update,
+            self,
+            &executor.context().1,
+            &executor.context().2,
+            &Some(scope_and_user.user_id.clone()),
+            scope_and_user.scope.clone(),
+            &scope_and_user.groups_scope,
+        )
+        .await
+        .map_err(|e| {
+            count_rejection(&executor.context().1, &e);
+            e
+        })?;
+        count_changes(&executor.context().1, &report);
+        Ok(ProfileUpdateResult {
+            profile,
+            changed_fields: report.changes,
+            warnings: report.warnings,
+        })
+    }
+
+    async fn preview_profile(update: InputProfile) -> GraphQlResult<ProfilePreview> {
+        let executor = &executor;
+        let scope_and_user = &executor.context().0;
+        if scope_and_user.scope == Trust::Public {
+            return Ok(ProfilePreview {
+                profile: Profile::default(),
+                changed_fields: vec![],
+            });
         }
+        preview_profile(
+            update,
+            self,
+            &executor.context().1,
+            &executor.context().2,
+            &Some(scope_and_user.user_id.clone()),
+            scope_and_user.scope.clone(),
+            &scope_and_user.groups_scope,
+        )
+        .await
     }
 }
 
-pub type Schema<T> = RootNode<
-    'static,
-    Query<T>,
-    Mutation<T>,
-    juniper::EmptySubscription<(ScopeAndUser, Arc<Metrics>)>,
->;
+pub struct Subscription<T: AsyncCisClientTrait> {
+    pub cis_client: T,
+    pub profile_bus: Arc<ProfileBus>,
+}
+
+type ProfileStream = Pin<Box<dyn Stream<Item = FieldResult<Profile>> + Send>>;
+
+/// Streams the current state of the profile for every matching event.
+fn profile_stream<T, F>(
+    subscription: &Subscription<T>,
+    context: &(ScopeAndUser, Arc<Metrics>, Arc<Loader>),
+    select: F,
+) -> ProfileStream
+where
+    T: AsyncCisClientTrait + Clone + Send + Sync + 'static,
+    F: Fn(&ProfileEvent) -> Option<Option<String>> + Send + 'static,
+{
+    let cis_client = subscription.cis_client.clone();
+    let (scope_and_user, metrics, _) = context;
+    let scope_and_user = scope_and_user.clone();
+    let metrics = Arc::clone(metrics);
+    let events = subscription.profile_bus.subscribe();
+    Box::pin(
+        events
+            .filter_map(move |event| ready(select(&event)))
+            .then(move |username| {
+                fetch_profile(
+                    cis_client.clone(),
+                    Arc::clone(&metrics),
+                    scope_and_user.clone(),
+                    username,
+                )
+            })
+            .map(|result| result.map_err(IntoFieldError::into_field_error)),
+    )
+}
+
+async fn fetch_profile(
+    cis_client: impl AsyncCisClientTrait,
+    metrics: Arc<Metrics>,
+    scope_and_user: ScopeAndUser,
+    username: Option<String>,
+) -> GraphQlResult<Profile> {
+    let params = get_profile_params(username, &scope_and_user, None)?;
+    let profile = metrics
+        .observe_upstream(
+            Upstream::Cis,
+            cis_client.get_user_by(&params.id, &params.by, Some(params.filter.as_str())),
+        )
+        .await?;
+    Ok(profile)
+}
+
+#[juniper::graphql_subscription{
+    Context = (ScopeAndUser, Arc<Metrics>, Arc<Loader>)
+}]
+impl<T: AsyncCisClientTrait + Clone + Send + Sync + 'static> Subscription<T> {
+    /// Own profile after every successful update.
+    async fn profile_updated() -> ProfileStream {
+        let executor = &executor;
+        let context = executor.context();
+        let user_id = context.0.user_id.clone();
+        profile_stream(self, context, move |event| {
+            (event.user_id == user_id).then_some(None)
+        })
+    }
+
+    /// Profiles of `usernames` after every successful update, filtered by
+    /// scope like the `profile` query.
+    async fn profiles_updated(usernames: Vec<String>) -> ProfileStream {
+        let executor = &executor;
+        profile_stream(self, executor.context(), move |event| {
+            usernames
+                .iter()
+                .any(|username| event.concerns(username))
+                .then(|| event.username.clone())
+        })
+    }
+}
+
+pub type Context = (ScopeAndUser, Arc<Metrics>, Arc<Loader>);
+
+pub type Schema<T> = RootNode<'static, Query<T>, Mutation<T>, Subscription<T>>;
 
 struct GetProfileParams {
     id: String,
@@ -202,7 +732,7 @@ fn get_profile_params(
     username: Option<String>,
     scope_and_user: &ScopeAndUser,
     view_as: Option<Display>,
-) -> Result<GetProfileParams, FieldError> {
+) -> GraphQlResult<GetProfileParams> {
     let scope: Display = scope_and_user.scope.clone().into();
     let params = if let Some(username) = username {
         // If a username has been provided we retrieve the
@@ -219,10 +749,7 @@ fn get_profile_params(
                     scope_and_user.user_id,
                     scope_and_user.scope.as_str()
                 );
-                return Err(field_error(
-                    "invalid_view_as",
-                    "Insufficient permission for requested view_as display level!",
-                ));
+                return Err(GraphQlError::InvalidViewAs);
             }
         } else {
             scope
@@ -248,12 +775,13 @@ fn get_profile_params(
 #[cfg(test)]
 mod root_test {
     use super::*;
+    use crate::graphql_api::input::ChangeKind;
     use dino_park_trust::AALevel;
     use dino_park_trust::GroupsTrust;
     use dino_park_trust::Trust;
 
     #[test]
-    fn test_get_filter_params_without_view_as() -> Result<(), FieldError> {
+    fn test_get_filter_params_without_view_as() -> Result<(), GraphQlError> {
         let username = Some(String::from("user1"));
         let scope_and_user = ScopeAndUser {
             user_id: String::from("user2"),
@@ -269,7 +797,7 @@ mod root_test {
     }
 
     #[test]
-    fn test_get_filter_params_with_view_as_pass() -> Result<(), FieldError> {
+    fn test_get_filter_params_with_view_as_pass() -> Result<(), GraphQlError> {
         let username = Some(String::from("user1"));
         let view_as = Some(Display::Ndaed);
         let scope_and_user = ScopeAndUser {
@@ -286,7 +814,7 @@ mod root_test {
     }
 
     #[test]
-    fn test_get_filter_params_with_view_as_fail() -> Result<(), FieldError> {
+    fn test_get_filter_params_with_view_as_fail() -> Result<(), GraphQlError> {
         let username = Some(String::from("user1"));
         let view_as = Some(Display::Ndaed);
         let scope_and_user = ScopeAndUser {
@@ -301,7 +829,7 @@ mod root_test {
     }
 
     #[test]
-    fn test_get_filter_params_self_without_view_as() -> Result<(), FieldError> {
+    fn test_get_filter_params_self_without_view_as() -> Result<(), GraphQlError> {
         let scope_and_user = ScopeAndUser {
             user_id: String::from("user1"),
             scope: Trust::Staff,
@@ -316,7 +844,7 @@ mod root_test {
     }
 
     #[test]
-    fn test_get_filter_params_self_with_higher_view_as() -> Result<(), FieldError> {
+    fn test_get_filter_params_self_with_higher_view_as() -> Result<(), GraphQlError> {
         let view_as = Some(Display::Staff);
         let scope_and_user = ScopeAndUser {
             user_id: String::from("user1"),
@@ -331,6 +859,51 @@ mod root_test {
         Ok(())
     }
 
+    #[test]
+    fn test_count_changes_and_rejections() -> Result<(), failure::Error> {
+        let metrics = Metrics::new()?;
+        let report = ChangeReport {
+            changes: vec![
+                FieldChange {
+                    field: String::from("fun_title"),
+                    kind: ChangeKind::Value,
+                },
+                FieldChange {
+                    field: String::from("fun_title"),
+                    kind: ChangeKind::Display,
+                },
+            ],
+            ..Default::default()
+        };
+        count_changes(&metrics, &report);
+        count_rejection(&metrics, &GraphQlError::UsernameTaken);
+        count_rejection(&metrics, &GraphQlError::ProfileNotFound);
+        let counters = &metrics.counters;
+        assert_eq!(counters.field_any_changed.get(), 1);
+        assert_eq!(
+            counters
+                .field_changed
+                .with_label_values(&["fun_title", "value"])
+                .get(),
+            1
+        );
+        assert_eq!(
+            counters
+                .update_rejected
+                .with_label_values(&["username_exists"])
+                .get(),
+            1
+        );
+        assert_eq!(
+            counters
+                .update_rejected
+                .with_label_values(&["profile_does_not_exist"])
+                .get(),
+            0
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_username() {
         assert!(valid_username("r--lwqkc13jeqw314").is_ok());
@@ -341,4 +914,13 @@ mod root_test {
         assert!(valid_username("a").is_err());
         assert!(valid_username("a".repeat(65).as_str()).is_err());
     }
+
+    #[test]
+    fn test_may_claim_reserved_as_admin() {
+        let reserved_names = ReservedNames::from_settings(&Default::default()).unwrap();
+        assert!(may_claim("fiji", &reserved_names, &GroupsTrust::None));
+        assert!(!may_claim("adm1n", &reserved_names, &GroupsTrust::None));
+        assert!(!may_claim("adm1n", &reserved_names, &GroupsTrust::Creator));
+        assert!(may_claim("adm1n", &reserved_names, &GroupsTrust::Admin));
+    }
 }
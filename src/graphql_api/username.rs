@@ -0,0 +1,157 @@
+use crate::settings::Usernames;
+use juniper::GraphQLEnum;
+use regex::Regex;
+
+/// Result of checking whether a username can be claimed.
+#[derive(GraphQLEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UsernameStatus {
+    Ok,
+    TooShort,
+    TooLong,
+    InvalidChars,
+    Taken,
+    Reserved,
+}
+
+const MIN_USERNAME_CHARS: usize = 2;
+const MAX_USERNAME_CHARS: usize = 64;
+
+/// Length and charset rules for usernames. `Taken` and `Reserved` depend on
+/// other profiles and settings and are left to the caller.
+pub fn username_status(username: &str) -> UsernameStatus {
+    let num_chars = username.chars().count();
+    if num_chars < MIN_USERNAME_CHARS {
+        return UsernameStatus::TooShort;
+    }
+    if num_chars > MAX_USERNAME_CHARS {
+        return UsernameStatus::TooLong;
+    }
+    let only_valid_chars = username
+        .chars()
+        .all(|c| (c.is_ascii_lowercase() || c.is_ascii_digit()) || c == '-' || c == '_');
+    if !only_valid_chars {
+        return UsernameStatus::InvalidChars;
+    }
+    UsernameStatus::Ok
+}
+
+/// Maps characters and sequences which look alike to a single
+/// representative, so `adm1n` or `a-d-m-i-n` compare equal to `admin`.
+fn skeleton(username: &str) -> String {
+    let mapped: String = username
+        .chars()
+        .filter(|c| *c != '-' && *c != '_')
+        .map(|c| match c.to_ascii_lowercase() {
+            '0' => 'o',
+            '1' | 'i' => 'l',
+            '3' => 'e',
+            '4' => 'a',
+            '5' => 's',
+            '7' => 't',
+            '8' => 'b',
+            c => c,
+        })
+        .collect();
+    mapped
+        .replace("rn", "m")
+        .replace("vv", "w")
+        .replace("cl", "d")
+}
+
+/// Usernames which may only be claimed by admins.
+pub struct ReservedNames {
+    exact: Vec<String>,
+    prefixes: Vec<String>,
+    patterns: Vec<Regex>,
+}
+
+impl ReservedNames {
+    pub fn from_settings(settings: &Usernames) -> Result<Self, regex::Error> {
+        Ok(ReservedNames {
+            exact: settings.reserved.iter().map(|s| skeleton(s)).collect(),
+            prefixes: settings
+                .reserved_prefixes
+                .iter()
+                .map(|s| skeleton(s))
+                .collect(),
+            patterns: settings
+                .reserved_patterns
+                .iter()
+                .map(|p| Regex::new(p))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn is_reserved(&self, username: &str) -> bool {
+        let normalized = skeleton(username);
+        self.exact.contains(&normalized)
+            || self.prefixes.iter().any(|p| normalized.starts_with(p))
+            || self.patterns.iter().any(|p| p.is_match(username))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn reserved() -> ReservedNames {
+        ReservedNames::from_settings(&Usernames {
+            reserved: vec![String::from("admin"), String::from("security")],
+            reserved_prefixes: vec![String::from("mozilla")],
+            reserved_patterns: vec![String::from("^[a-z]+-staff$")],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_username_status_length() {
+        assert_eq!(username_status("a"), UsernameStatus::TooShort);
+        assert_eq!(username_status("ab"), UsernameStatus::Ok);
+        assert_eq!(username_status(&"a".repeat(64)), UsernameStatus::Ok);
+        assert_eq!(username_status(&"a".repeat(65)), UsernameStatus::TooLong);
+    }
+
+    #[test]
+    fn test_username_status_chars() {
+        assert_eq!(username_status("a-b_1"), UsernameStatus::Ok);
+        assert_eq!(username_status("aB"), UsernameStatus::InvalidChars);
+        assert_eq!(username_status("a b"), UsernameStatus::InvalidChars);
+        assert_eq!(username_status("äb"), UsernameStatus::InvalidChars);
+    }
+
+    #[test]
+    fn test_reserved_exact() {
+        let reserved = reserved();
+        assert!(reserved.is_reserved("admin"));
+        assert!(reserved.is_reserved("security"));
+        assert!(!reserved.is_reserved("admins"));
+        assert!(!reserved.is_reserved("fiji"));
+    }
+
+    #[test]
+    fn test_reserved_confusables() {
+        let reserved = reserved();
+        assert!(reserved.is_reserved("adm1n"));
+        assert!(reserved.is_reserved("a-d_min"));
+        assert!(reserved.is_reserved("acirnin"));
+        assert!(reserved.is_reserved("5ecur1ty"));
+    }
+
+    #[test]
+    fn test_reserved_prefix_and_pattern() {
+        let reserved = reserved();
+        assert!(reserved.is_reserved("mozilla-it"));
+        assert!(reserved.is_reserved("m0zi11a"));
+        assert!(reserved.is_reserved("infra-staff"));
+        assert!(!reserved.is_reserved("infra-staffer"));
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        assert!(ReservedNames::from_settings(&Usernames {
+            reserved_patterns: vec![String::from("(")],
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
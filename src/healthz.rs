@@ -1,11 +1,180 @@
+use crate::settings::Settings;
 use actix_web::dev::HttpServiceFactory;
 use actix_web::web;
+use actix_web::web::Data;
 use actix_web::HttpResponse;
+use chrono::DateTime;
+use chrono::Utc;
+use futures::future::join_all;
+use futures::lock::Mutex;
+use log::warn;
+use reqwest::Client;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Public, so errors are only logged.
+#[derive(Serialize, Clone)]
+struct DependencyStatus {
+    name: &'static str,
+    healthy: bool,
+    latency_ms: u64,
+}
+
+#[derive(Serialize, Clone)]
+struct ReadinessReport {
+    healthy: bool,
+    checked_at: DateTime<Utc>,
+    dependencies: Vec<DependencyStatus>,
+}
+
+/// Checks the reachability of all upstream services and caches the result
+/// so probes don't hammer the upstreams. Only one check runs at a time,
+/// concurrent probes wait for its result.
+pub struct Readiness {
+    client: Client,
+    dependencies: Vec<(&'static str, String)>,
+    timeout: Duration,
+    ttl: Duration,
+    cache: Mutex<Option<(Instant, ReadinessReport)>>,
+}
+
+impl Readiness {
+    pub fn new(settings: &Settings, client: Client) -> Self {
+        let dino_park = &settings.dino_park;
+        let mut dependencies = vec![
+            ("cis", settings.cis.person_api_user_endpoint.clone()),
+            ("orgchart", dino_park.orgchart.full_endpoint.clone()),
+            ("search", dino_park.search.simple_endpoint.clone()),
+            ("fossil", dino_park.fossil.upload_endpoint.clone()),
+        ];
+        if dino_park.lookout.internal_update_enabled {
+            dependencies.push((
+                "lookout",
+                dino_park.lookout.internal_update_endpoint.clone(),
+            ));
+        }
+        Readiness {
+            client,
+            dependencies,
+            timeout: Duration::from_millis(settings.readiness.timeout_ms),
+            ttl: Duration::from_secs(settings.readiness.cache_ttl_secs),
+            cache: Mutex::new(None),
+        }
+    }
+
+    async fn check(&self, name: &'static str, endpoint: &str) -> DependencyStatus {
+        let start = Instant::now();
+        // Any answer below 500 means the upstream is up. HEAD keeps the
+        // upstreams from building e.g. the full orgchart for us.
+        let error = match self
+            .client
+            .head(endpoint)
+            .timeout(self.timeout)
+            .send()
+            .await
+        {
+            Ok(res) if res.status().is_server_error() => Some(res.status().to_string()),
+            Ok(_) => None,
+            Err(e) => Some(e.to_string()),
+        };
+        if let Some(e) = &error {
+            warn!("upstream {} is unhealthy: {}", name, e);
+        }
+        DependencyStatus {
+            name,
+            healthy: error.is_none(),
+            latency_ms: start.elapsed().as_millis() as u64,
+        }
+    }
+
+    async fn report(&self) -> ReadinessReport {
+        let mut cache = self.cache.lock().await;
+        if let Some((checked, report)) = &*cache {
+            if checked.elapsed() < self.ttl {
+                return report.clone();
+            }
+        }
+        let dependencies = join_all(
+            self.dependencies
+                .iter()
+                .map(|(name, endpoint)| self.check(name, endpoint)),
+        )
+        .await;
+        let report = ReadinessReport {
+            healthy: dependencies.iter().all(|d| d.healthy),
+            checked_at: Utc::now(),
+            dependencies,
+        };
+        *cache = Some((Instant::now(), report.clone()));
+        report
+    }
+}
 
 async fn healthz() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
 
-pub fn healthz_app() -> impl HttpServiceFactory {
-    web::scope("/healthz").service(web::resource("").to(healthz))
+async fn healthz_deep(readiness: Data<Readiness>) -> HttpResponse {
+    let report = readiness.report().await;
+    if report.healthy {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+/// Only checks this replica. Upstreams are shared by all replicas, taking
+/// them all out of rotation when one is down would not help anyone.
+async fn readyz() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+pub fn healthz_app(readiness: &Data<Readiness>) -> impl HttpServiceFactory {
+    web::scope("/healthz")
+        .app_data(readiness.clone())
+        .service(web::resource("").to(healthz))
+        .service(web::resource("/deep").to(healthz_deep))
+}
+
+pub fn readyz_app() -> impl HttpServiceFactory {
+    web::scope("/readyz").service(web::resource("").to(readyz))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn readiness(endpoint: &str) -> Readiness {
+        Readiness {
+            client: Client::new(),
+            dependencies: vec![("unreachable", String::from(endpoint))],
+            timeout: Duration::from_secs(2),
+            ttl: Duration::from_secs(60),
+            cache: Mutex::new(None),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_dependency_is_unhealthy() {
+        let readiness = readiness("http://127.0.0.1:1/");
+        let report = readiness.report().await;
+        assert!(!report.healthy);
+        assert_eq!(report.dependencies.len(), 1);
+        assert!(!report.dependencies[0].healthy);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_probes_share_one_check() {
+        let readiness = readiness("http://127.0.0.1:1/");
+        let (first, second) = futures::join!(readiness.report(), readiness.report());
+        assert_eq!(first.checked_at, second.checked_at);
+    }
+
+    #[tokio::test]
+    async fn test_report_is_cached() {
+        let readiness = readiness("http://127.0.0.1:1/");
+        let first = readiness.report().await;
+        let second = readiness.report().await;
+        assert_eq!(first.checked_at, second.checked_at);
+    }
 }
@@ -3,22 +3,45 @@ extern crate juniper;
 #[macro_use]
 extern crate serde_derive;
 
+mod client;
+mod db;
 mod error;
 mod graphql_api;
 mod healthz;
 mod metrics;
 mod orgchart;
+mod outbound;
+mod picture;
 mod proxy;
+mod ratelimit;
 mod search;
 mod session;
 mod settings;
+mod usernames;
 
+use crate::client::client_from_settings;
+use crate::db::pool_from_settings;
 use crate::graphql_api::app::graphql_app;
+use crate::graphql_api::bus::ProfileBus;
+use crate::graphql_api::username::ReservedNames;
 use crate::healthz::healthz_app;
+use crate::healthz::readyz_app;
+use crate::healthz::Readiness;
 use crate::metrics::metrics_app;
+use crate::metrics::RequestMetrics;
 use crate::orgchart::app::orgchart_app;
+use crate::orgchart::cache::OrgchartCache;
+use crate::outbound::Outbound;
+use crate::picture::app::picture_app;
+use crate::ratelimit::RateLimiter;
+use crate::ratelimit::RateLimiting;
+use crate::ratelimit::API_PREFIX;
 use crate::search::app::search_app;
 use crate::session::app::session_app;
+use crate::usernames::app::usernames_app;
+use crate::usernames::history::MemoryHistory;
+use crate::usernames::history::PostgresHistory;
+use crate::usernames::history::UsernameHistory;
 
 use actix_web::middleware::Logger;
 use actix_web::web;
@@ -29,7 +52,9 @@ use cis_client::CisClient;
 use dino_park_gate::provider::Provider;
 use dino_park_gate::scope::ScopeAndUserAuth;
 use log::info;
+use log::warn;
 use std::io::Error;
+use std::sync::Arc;
 
 fn map_io_err(e: impl Into<failure::Error>) -> Error {
     Error::other(e.into())
@@ -46,23 +71,73 @@ async fn main() -> std::io::Result<()> {
     let m = metrics::Metrics::new().map_err(map_io_err)?;
     let s = settings::Settings::new().map_err(map_io_err)?;
     let cis_client = CisClient::from_settings(&s.cis).await.map_err(map_io_err)?;
+    let client = client_from_settings(&s.http_client).map_err(map_io_err)?;
+    let readiness = Data::new(Readiness::new(&s, client.clone()));
     let dino_park_settings = s.dino_park;
+    let orgchart_cache = Arc::new(OrgchartCache::from_settings(
+        &dino_park_settings.orgchart.cache,
+    ));
+    let outbound = Arc::new(Outbound::new(client, &dino_park_settings.outbound, &m));
+    let username_limiter = Arc::new(RateLimiter::new(
+        &dino_park_settings.rate_limits.username_available,
+    ));
+    let rate_limiting = RateLimiting::new(&dino_park_settings.rate_limits, &m);
+    let pool = pool_from_settings(&s.database).await.map_err(map_io_err)?;
+    let grace = chrono::Duration::days(i64::from(dino_park_settings.usernames.history_grace_days));
+    let username_history: Arc<dyn UsernameHistory> = match &pool {
+        Some(pool) => Arc::new(PostgresHistory::new(pool.clone(), grace)),
+        None => {
+            warn!("no database configured, username history and profile events stay local");
+            Arc::new(MemoryHistory::new(grace))
+        }
+    };
+    let profile_bus = Arc::new(ProfileBus::new(pool));
+    if let Some(url) = s.database.url {
+        actix_rt::spawn(Arc::clone(&profile_bus).listen(url));
+    }
+    let reserved_names =
+        Arc::new(ReservedNames::from_settings(&dino_park_settings.usernames).map_err(map_io_err)?);
     let provider = Provider::from_issuer(&s.auth).await.map_err(map_io_err)?;
     // Start http server
     HttpServer::new(move || {
         let scope_middleware = ScopeAndUserAuth::new(provider.clone()).public();
         App::new()
-            .wrap(Logger::default().exclude("/healthz"))
+            .wrap(Logger::default().exclude("/healthz").exclude("/readyz"))
+            .wrap(RequestMetrics::new(&m))
             .app_data(Data::new(m.clone()))
             .service(
-                web::scope("/api/v4")
+                web::scope(API_PREFIX)
+                    // registered first to run after the scope middleware
+                    .wrap(rate_limiting.clone())
                     .wrap(scope_middleware)
-                    .service(graphql_app(cis_client.clone(), &dino_park_settings))
-                    .service(search_app(&dino_park_settings.search))
-                    .service(orgchart_app(&dino_park_settings.orgchart)),
+                    .service(graphql_app(
+                        cis_client.clone(),
+                        &dino_park_settings,
+                        &orgchart_cache,
+                        &outbound,
+                        &username_limiter,
+                        &reserved_names,
+                        &username_history,
+                        &profile_bus,
+                    ))
+                    .service(search_app(&dino_park_settings.search, &outbound))
+                    .service(picture_app(
+                        cis_client.clone(),
+                        &dino_park_settings.picture,
+                        &dino_park_settings.fossil,
+                        &outbound,
+                    ))
+                    .service(orgchart_app(
+                        &dino_park_settings.orgchart,
+                        &orgchart_cache,
+                        &outbound,
+                        &username_history,
+                    ))
+                    .service(usernames_app(&username_history)),
             )
             .service(session_app())
-            .service(healthz_app())
+            .service(healthz_app(&readiness))
+            .service(readyz_app())
             .service(metrics_app())
     })
     .bind("0.0.0.0:8081")?
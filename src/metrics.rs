@@ -1,33 +1,222 @@
+use actix_web::dev::forward_ready;
 use actix_web::dev::HttpServiceFactory;
+use actix_web::dev::Service;
+use actix_web::dev::ServiceRequest;
+use actix_web::dev::ServiceResponse;
+use actix_web::dev::Transform;
 use actix_web::web;
 use actix_web::HttpRequest;
 use actix_web::HttpResponse;
 use failure::Error;
+use futures::future::ready;
+use futures::future::LocalBoxFuture;
+use futures::future::Ready;
 use prometheus::Encoder;
+use prometheus::HistogramOpts;
+use prometheus::HistogramVec;
 use prometheus::IntCounter;
+use prometheus::IntCounterVec;
+use prometheus::Opts;
 use prometheus::Registry;
 use prometheus::TextEncoder;
+use std::future::Future;
+use std::time::Instant;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Upstream {
+    Cis,
+    Search,
+    Tree,
+    Fossil,
+    Lookout,
+}
+
+impl Upstream {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Upstream::Cis => "cis",
+            Upstream::Search => "search",
+            Upstream::Tree => "tree",
+            Upstream::Fossil => "fossil",
+            Upstream::Lookout => "lookout",
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Counters {
     pub field_any_changed: IntCounter,
+    pub field_changed: IntCounterVec,
+    pub update_rejected: IntCounterVec,
+    pub orgchart_cache_hits: IntCounter,
+    pub orgchart_cache_misses: IntCounter,
+    pub rate_limited: IntCounterVec,
+    pub graphql_rejected: IntCounterVec,
+}
+
+#[derive(Clone)]
+pub struct Histograms {
+    pub request_duration: HistogramVec,
+    pub upstream_duration: HistogramVec,
 }
 
 #[derive(Clone)]
 pub struct Metrics {
     pub registry: Registry,
     pub counters: Counters,
+    pub histograms: Histograms,
 }
 
 impl Metrics {
     pub fn new() -> Result<Self, Error> {
         let counters = Counters {
             field_any_changed: IntCounter::new("field_any_changed_counter", "field changed")?,
+            field_changed: IntCounterVec::new(
+                Opts::new("field_changed_counter", "field changed by field and kind"),
+                &["field", "kind"],
+            )?,
+            update_rejected: IntCounterVec::new(
+                Opts::new("update_rejected_counter", "rejected updates by reason"),
+                &["reason"],
+            )?,
+            orgchart_cache_hits: IntCounter::new(
+                "orgchart_cache_hits_counter",
+                "orgchart responses served from cache",
+            )?,
+            orgchart_cache_misses: IntCounter::new(
+                "orgchart_cache_misses_counter",
+                "orgchart responses fetched from upstream",
+            )?,
+            rate_limited: IntCounterVec::new(
+                Opts::new("rate_limited_counter", "throttled requests by route"),
+                &["route"],
+            )?,
+            graphql_rejected: IntCounterVec::new(
+                Opts::new("graphql_rejected_counter", "rejected queries by limit"),
+                &["reason"],
+            )?,
+        };
+        let histograms = Histograms {
+            request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "request_duration_seconds",
+                    "request duration by route and status",
+                ),
+                &["method", "route", "status"],
+            )?,
+            upstream_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "upstream_duration_seconds",
+                    "duration of outbound calls by upstream",
+                ),
+                &["upstream"],
+            )?,
         };
         let registry = Registry::new();
         registry.register(Box::new(counters.field_any_changed.clone()))?;
+        registry.register(Box::new(counters.field_changed.clone()))?;
+        registry.register(Box::new(counters.update_rejected.clone()))?;
+        registry.register(Box::new(counters.orgchart_cache_hits.clone()))?;
+        registry.register(Box::new(counters.orgchart_cache_misses.clone()))?;
+        registry.register(Box::new(counters.rate_limited.clone()))?;
+        registry.register(Box::new(counters.graphql_rejected.clone()))?;
+        registry.register(Box::new(histograms.request_duration.clone()))?;
+        registry.register(Box::new(histograms.upstream_duration.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            counters,
+            histograms,
+        })
+    }
+
+    /// Runs an outbound call and records its duration for `upstream`.
+    pub async fn observe_upstream<T>(
+        &self,
+        upstream: Upstream,
+        call: impl Future<Output = T>,
+    ) -> T {
+        let start = Instant::now();
+        let res = call.await;
+        self.histograms
+            .upstream_duration
+            .with_label_values(&[upstream.as_str()])
+            .observe(start.elapsed().as_secs_f64());
+        res
+    }
+}
+
+/// Middleware recording the duration and status of every request.
+pub struct RequestMetrics {
+    request_duration: HistogramVec,
+}
+
+impl RequestMetrics {
+    pub fn new(metrics: &Metrics) -> Self {
+        RequestMetrics {
+            request_duration: metrics.histograms.request_duration.clone(),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware {
+            service,
+            request_duration: self.request_duration.clone(),
+        }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: S,
+    request_duration: HistogramVec,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
 
-        Ok(Metrics { registry, counters })
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let method = req.method().to_string();
+        // Use the route pattern instead of the path to keep the label
+        // cardinality bounded.
+        let route = req
+            .match_pattern()
+            .unwrap_or_else(|| String::from("unmatched"));
+        let request_duration = self.request_duration.clone();
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await;
+            let status = match &res {
+                Ok(res) => res.status(),
+                Err(e) => e.as_response_error().status_code(),
+            };
+            request_duration
+                .with_label_values(&[&method, &route, status.as_str()])
+                .observe(start.elapsed().as_secs_f64());
+            res
+        })
     }
 }
 
@@ -1,16 +1,26 @@
 use crate::error::ApiError;
-use crate::proxy::proxy;
+use crate::metrics::Metrics;
+use crate::metrics::Upstream;
+use crate::orgchart::cache::CacheKey;
+use crate::orgchart::cache::CachedResponse;
+use crate::orgchart::cache::OrgchartCache;
+use crate::outbound::Outbound;
+use crate::proxy::fetch;
 use crate::settings::Orgchart;
+use crate::usernames::app::resolve;
+use crate::usernames::history::UsernameHistory;
 use actix_web::dev::HttpServiceFactory;
+use actix_web::http::header;
 use actix_web::web;
 use actix_web::web::Data;
 use actix_web::web::Path;
+use actix_web::HttpRequest;
 use actix_web::HttpResponse;
 use dino_park_guard::guard;
 use percent_encoding::utf8_percent_encode;
 use percent_encoding::AsciiSet;
 use percent_encoding::CONTROLS;
-use reqwest::Client;
+use std::sync::Arc;
 
 pub const USERINFO_ENCODE_SET: &AsciiSet = &CONTROLS
     .add(b' ')
@@ -33,49 +43,152 @@ pub const USERINFO_ENCODE_SET: &AsciiSet = &CONTROLS
     .add(b'^')
     .add(b'|');
 
+fn respond(req: &HttpRequest, cached: CachedResponse) -> HttpResponse {
+    let not_modified = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| cached.matches(v))
+        .unwrap_or_default();
+    if not_modified {
+        return HttpResponse::NotModified()
+            .insert_header((header::ETAG, cached.etag))
+            .finish();
+    }
+    let mut response = HttpResponse::Ok();
+    response.insert_header((header::ETAG, cached.etag));
+    if let Some(content_type) = cached.content_type {
+        response.insert_header((header::CONTENT_TYPE, content_type));
+    }
+    if let Some(cache_control) = cached.cache_control {
+        response.insert_header((header::CACHE_CONTROL, cache_control));
+    }
+    response.body(cached.body)
+}
+
+/// On a cache miss for a username in `history`, `moved` is checked before
+/// fetching. Hits don't need it, the cache is cleared on profile updates.
+async fn cached(
+    req: &HttpRequest,
+    outbound: &Outbound,
+    metrics: &Metrics,
+    cache: &OrgchartCache,
+    history: Option<(&dyn UsernameHistory, &str)>,
+    key: CacheKey,
+    endpoint: &str,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(cached) = cache.get(&key) {
+        metrics.counters.orgchart_cache_hits.inc();
+        return Ok(respond(req, cached));
+    }
+    metrics.counters.orgchart_cache_misses.inc();
+    if let Some((history, username)) = history {
+        if let Some(redirect) = moved(history, username).await? {
+            return Ok(redirect);
+        }
+    }
+    let fetched = CachedResponse::from(fetch(outbound, Upstream::Tree, endpoint).await?);
+    cache.insert(key, fetched.clone());
+    Ok(respond(req, fetched))
+}
+
+/// Redirects requests for a previous username to the current one.
+async fn moved(
+    history: &dyn UsernameHistory,
+    username: &str,
+) -> Result<Option<HttpResponse>, ApiError> {
+    Ok(resolve(history, username).await?.map(|current| {
+        // relative to the requested path, so only the username is replaced
+        let location = utf8_percent_encode(&current, USERINFO_ENCODE_SET).to_string();
+        HttpResponse::TemporaryRedirect()
+            .insert_header((header::LOCATION, location))
+            .finish()
+    }))
+}
+
 #[guard(Staff)]
 async fn handle_full(
-    client: Data<Client>,
+    req: HttpRequest,
+    outbound: Data<Arc<Outbound>>,
+    metrics: Data<Metrics>,
+    cache: Data<Arc<OrgchartCache>>,
     state: Data<Orgchart>,
 ) -> Result<HttpResponse, ApiError> {
-    proxy(&client, &state.full_endpoint).await
+    cached(
+        &req,
+        &outbound,
+        &metrics,
+        &cache,
+        None,
+        CacheKey::Full,
+        &state.full_endpoint,
+    )
+    .await
 }
 
 #[guard(Staff)]
+#[allow(clippy::too_many_arguments)]
 async fn handle_trace(
-    client: Data<Client>,
+    req: HttpRequest,
+    outbound: Data<Arc<Outbound>>,
+    metrics: Data<Metrics>,
+    cache: Data<Arc<OrgchartCache>>,
+    history: Data<Arc<dyn UsernameHistory>>,
     state: Data<Orgchart>,
     username: Path<String>,
 ) -> Result<HttpResponse, ApiError> {
+    let username = username.into_inner();
     let safe_username = utf8_percent_encode(&username, USERINFO_ENCODE_SET);
-    proxy(
-        &client,
-        &format!("{}{}", state.trace_endpoint, safe_username),
+    let endpoint = format!("{}{}", state.trace_endpoint, safe_username);
+    cached(
+        &req,
+        &outbound,
+        &metrics,
+        &cache,
+        Some((&***history, username.as_str())),
+        CacheKey::Trace(username.clone()),
+        &endpoint,
     )
     .await
 }
 
 #[guard(Staff)]
+#[allow(clippy::too_many_arguments)]
 async fn handle_related(
-    client: Data<Client>,
+    req: HttpRequest,
+    outbound: Data<Arc<Outbound>>,
+    metrics: Data<Metrics>,
+    cache: Data<Arc<OrgchartCache>>,
+    history: Data<Arc<dyn UsernameHistory>>,
     state: Data<Orgchart>,
     username: Path<String>,
 ) -> Result<HttpResponse, ApiError> {
+    let username = username.into_inner();
     let safe_username = utf8_percent_encode(&username, USERINFO_ENCODE_SET);
-    proxy(
-        &client,
-        &format!("{}{}", state.related_endpoint, safe_username),
+    let endpoint = format!("{}{}", state.related_endpoint, safe_username);
+    cached(
+        &req,
+        &outbound,
+        &metrics,
+        &cache,
+        Some((&***history, username.as_str())),
+        CacheKey::Related(username.clone()),
+        &endpoint,
     )
     .await
 }
 
-pub fn orgchart_app(settings: &Orgchart) -> impl HttpServiceFactory {
-    // SAFETY: this panics.
-    // https://docs.rs/reqwest/latest/reqwest/struct.Client.html#panics
-    let client = Client::new();
+pub fn orgchart_app(
+    settings: &Orgchart,
+    cache: &Arc<OrgchartCache>,
+    outbound: &Arc<Outbound>,
+    history: &Arc<dyn UsernameHistory>,
+) -> impl HttpServiceFactory {
     web::scope("/orgchart")
         .app_data(Data::new(settings.clone()))
-        .app_data(Data::new(client))
+        .app_data(Data::new(Arc::clone(history)))
+        .app_data(Data::new(Arc::clone(outbound)))
+        .app_data(Data::new(Arc::clone(cache)))
         .service(web::resource("").route(web::get().to(handle_full)))
         .service(web::resource("/related/{username}").route(web::get().to(handle_related)))
         .service(web::resource("/trace/{username}").route(web::get().to(handle_trace)))
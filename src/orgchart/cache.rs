@@ -0,0 +1,189 @@
+use crate::proxy::Fetched;
+use crate::settings::Cache;
+use actix_web::web::Bytes;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CacheKey {
+    Full,
+    Related(String),
+    Trace(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct CachedResponse {
+    pub body: Bytes,
+    pub content_type: Option<String>,
+    pub etag: String,
+    pub cache_control: Option<String>,
+}
+
+impl From<Fetched> for CachedResponse {
+    fn from(fetched: Fetched) -> Self {
+        let Fetched {
+            body,
+            content_type,
+            etag,
+            cache_control,
+        } = fetched;
+        // DinoPark Tree doesn't send ETags (yet), so derive one from the body.
+        let etag = etag.unwrap_or_else(|| {
+            let mut hasher = DefaultHasher::new();
+            body.hash(&mut hasher);
+            format!("\"{:x}\"", hasher.finish())
+        });
+        CachedResponse {
+            body,
+            content_type,
+            etag,
+            cache_control,
+        }
+    }
+}
+
+impl CachedResponse {
+    /// Whether an `If-None-Match` header value matches this response.
+    pub fn matches(&self, if_none_match: &str) -> bool {
+        let etag = self.etag.trim_start_matches("W/");
+        if_none_match
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag)
+    }
+}
+
+struct Entry {
+    response: CachedResponse,
+    inserted: Instant,
+}
+
+pub struct OrgchartCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<CacheKey, Entry>>,
+}
+
+impl OrgchartCache {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        OrgchartCache {
+            ttl,
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn from_settings(settings: &Cache) -> Self {
+        Self::new(Duration::from_secs(settings.ttl_secs), settings.max_entries)
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<CachedResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) if entry.inserted.elapsed() < self.ttl => Some(entry.response.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, key: CacheKey, response: CachedResponse) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let ttl = self.ttl;
+        entries.retain(|_, entry| entry.inserted.elapsed() < ttl);
+        while entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.inserted)
+                .map(|(key, _)| key.clone());
+            match oldest {
+                Some(oldest) => entries.remove(&oldest),
+                None => break,
+            };
+        }
+        entries.insert(
+            key,
+            Entry {
+                response,
+                inserted: Instant::now(),
+            },
+        );
+    }
+
+    /// Evicts all entries. A profile update can change the related and trace
+    /// views of colleagues as well, so nothing cached is safe to keep.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn response(body: &'static str) -> CachedResponse {
+        CachedResponse::from(Fetched {
+            body: Bytes::from(body),
+            content_type: None,
+            etag: None,
+            cache_control: None,
+        })
+    }
+
+    #[test]
+    fn test_get_returns_inserted() {
+        let cache = OrgchartCache::new(Duration::from_secs(60), 10);
+        cache.insert(CacheKey::Full, response("full"));
+        assert_eq!(cache.get(&CacheKey::Full).unwrap().body, "full");
+        assert!(cache.get(&CacheKey::Related(String::from("a"))).is_none());
+    }
+
+    #[test]
+    fn test_expired_entries_are_dropped() {
+        let cache = OrgchartCache::new(Duration::from_secs(0), 10);
+        cache.insert(CacheKey::Full, response("full"));
+        assert!(cache.get(&CacheKey::Full).is_none());
+    }
+
+    #[test]
+    fn test_oldest_entry_is_evicted() {
+        let cache = OrgchartCache::new(Duration::from_secs(60), 2);
+        cache.insert(CacheKey::Related(String::from("a")), response("a"));
+        cache.insert(CacheKey::Related(String::from("b")), response("b"));
+        cache.insert(CacheKey::Related(String::from("c")), response("c"));
+        assert!(cache.get(&CacheKey::Related(String::from("a"))).is_none());
+        assert!(cache.get(&CacheKey::Related(String::from("b"))).is_some());
+        assert!(cache.get(&CacheKey::Related(String::from("c"))).is_some());
+    }
+
+    #[test]
+    fn test_clear() {
+        let cache = OrgchartCache::new(Duration::from_secs(60), 10);
+        cache.insert(CacheKey::Full, response("full"));
+        cache.insert(CacheKey::Trace(String::from("a")), response("a"));
+        cache.insert(CacheKey::Related(String::from("b")), response("b"));
+        cache.clear();
+        assert!(cache.get(&CacheKey::Full).is_none());
+        assert!(cache.get(&CacheKey::Trace(String::from("a"))).is_none());
+        assert!(cache.get(&CacheKey::Related(String::from("b"))).is_none());
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        let r = response("full");
+        assert!(r.matches(&r.etag));
+        assert!(r.matches(&format!("\"other\", W/{}", r.etag)));
+        assert!(r.matches("*"));
+        assert!(!r.matches("\"other\""));
+    }
+}
@@ -0,0 +1,285 @@
+use crate::error::ApiError;
+use crate::metrics::Metrics;
+use crate::metrics::Upstream;
+use crate::settings;
+use crate::settings::UpstreamPolicy;
+use actix_web::http::StatusCode;
+use log::error;
+use log::warn;
+use reqwest::Client;
+use reqwest::IntoUrl;
+use reqwest::Method;
+use reqwest::Request;
+use reqwest::RequestBuilder;
+use reqwest::Response;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Tracks consecutive failures of an upstream. Once `failure_threshold` is
+/// reached the circuit opens and calls fail fast for `open_for`. After that a
+/// single trial call is let through, which either closes the circuit again
+/// or keeps it open for another period.
+#[derive(Default)]
+struct Breaker {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl Breaker {
+    fn allow(&mut self, open_for: Duration) -> bool {
+        match self.open_until {
+            Some(until) if Instant::now() < until => false,
+            Some(_) => {
+                // half open: let this call through and hold back the rest
+                self.open_until = Some(Instant::now() + open_for);
+                true
+            }
+            None => true,
+        }
+    }
+
+    fn success(&mut self) {
+        self.failures = 0;
+        self.open_until = None;
+    }
+
+    fn failure(&mut self, threshold: u32, open_for: Duration) {
+        self.failures = self.failures.saturating_add(1);
+        if threshold > 0 && self.failures >= threshold {
+            self.open_until = Some(Instant::now() + open_for);
+        }
+    }
+}
+
+struct Guard {
+    policy: UpstreamPolicy,
+    breaker: Mutex<Breaker>,
+}
+
+impl Guard {
+    fn new(policy: &UpstreamPolicy) -> Self {
+        Guard {
+            policy: policy.clone(),
+            breaker: Mutex::new(Breaker::default()),
+        }
+    }
+
+    fn open_for(&self) -> Duration {
+        Duration::from_secs(self.policy.open_secs)
+    }
+
+    fn allow(&self) -> bool {
+        self.policy.failure_threshold == 0 || self.breaker.lock().unwrap().allow(self.open_for())
+    }
+
+    fn record(&self, healthy: bool) {
+        let mut breaker = self.breaker.lock().unwrap();
+        if healthy {
+            breaker.success();
+        } else {
+            breaker.failure(self.policy.failure_threshold, self.open_for());
+        }
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD)
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Shared client for all calls to DinoPark upstreams applying the
+/// per-upstream timeout, retry and circuit breaker policies.
+pub struct Outbound {
+    client: Client,
+    metrics: Metrics,
+    guards: HashMap<Upstream, Guard>,
+    fallback: Guard,
+}
+
+impl Outbound {
+    pub fn new(client: Client, settings: &settings::Outbound, metrics: &Metrics) -> Self {
+        let guards = vec![
+            (Upstream::Search, Guard::new(&settings.search)),
+            (Upstream::Tree, Guard::new(&settings.tree)),
+            (Upstream::Fossil, Guard::new(&settings.fossil)),
+            (Upstream::Lookout, Guard::new(&settings.lookout)),
+        ]
+        .into_iter()
+        .collect();
+        Outbound {
+            client,
+            metrics: metrics.clone(),
+            guards,
+            fallback: Guard::new(&UpstreamPolicy::default()),
+        }
+    }
+
+    fn guard(&self, upstream: Upstream) -> &Guard {
+        self.guards.get(&upstream).unwrap_or(&self.fallback)
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn request<U: IntoUrl>(&self, method: Method, url: U) -> RequestBuilder {
+        self.client.request(method, url)
+    }
+
+    pub fn get<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    /// Sends `req` to `upstream`. Idempotent requests are retried on
+    /// connection errors and gateway errors. Responses are returned as is,
+    /// checking the status is up to the caller.
+    pub async fn send(
+        &self,
+        upstream: Upstream,
+        req: RequestBuilder,
+    ) -> Result<Response, ApiError> {
+        let guard = self.guard(upstream);
+        if !guard.allow() {
+            warn!("circuit open for {}", upstream.as_str());
+            return Err(ApiError::UpstreamUnavailable(upstream.as_str()));
+        }
+        let request = req
+            .timeout(Duration::from_millis(guard.policy.timeout_ms))
+            .build()
+            .map_err(|e| {
+                error!("invalid request for {}: {}", upstream.as_str(), e);
+                ApiError::ProxyError
+            })?;
+        let retries = if is_idempotent(request.method()) {
+            guard.policy.retries
+        } else {
+            0
+        };
+        let mut attempt = 0;
+        let mut request = Some(request);
+        loop {
+            let current = request.take().ok_or(ApiError::ProxyError)?;
+            let next = if attempt < retries {
+                // requests with streaming bodies can't be cloned and are not retried
+                current.try_clone()
+            } else {
+                None
+            };
+            let res = self.execute(upstream, current).await;
+            let healthy = matches!(&res, Ok(r) if !r.status().is_server_error());
+            guard.record(healthy);
+            let retry = match &res {
+                Ok(r) => is_retryable(r.status()),
+                Err(e) => e.is_connect() || e.is_timeout(),
+            };
+            match (retry, next) {
+                (true, Some(next)) => {
+                    let backoff = guard.policy.retry_backoff_ms << attempt.min(10);
+                    warn!(
+                        "retrying {} in {}ms (attempt {})",
+                        upstream.as_str(),
+                        backoff,
+                        attempt + 1
+                    );
+                    actix_rt::time::sleep(Duration::from_millis(backoff)).await;
+                    attempt += 1;
+                    request = Some(next);
+                }
+                _ => {
+                    return res.map_err(|e| {
+                        error!("{} error: {}", upstream.as_str(), e);
+                        ApiError::ProxyError
+                    })
+                }
+            }
+        }
+    }
+
+    async fn execute(&self, upstream: Upstream, request: Request) -> reqwest::Result<Response> {
+        self.metrics
+            .observe_upstream(upstream, self.client.execute(request))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use failure::Error;
+
+    fn policy(retries: u32, failure_threshold: u32) -> UpstreamPolicy {
+        UpstreamPolicy {
+            timeout_ms: 500,
+            retries,
+            retry_backoff_ms: 1,
+            failure_threshold,
+            open_secs: 60,
+        }
+    }
+
+    fn outbound(policy: UpstreamPolicy) -> Result<Outbound, Error> {
+        let settings = settings::Outbound {
+            search: policy,
+            ..Default::default()
+        };
+        Ok(Outbound::new(Client::new(), &settings, &Metrics::new()?))
+    }
+
+    #[test]
+    fn test_breaker_opens_after_threshold() {
+        let open_for = Duration::from_secs(60);
+        let mut breaker = Breaker::default();
+        breaker.failure(2, open_for);
+        assert!(breaker.allow(open_for));
+        breaker.failure(2, open_for);
+        assert!(!breaker.allow(open_for));
+    }
+
+    #[test]
+    fn test_breaker_half_open_allows_single_trial() {
+        let mut breaker = Breaker::default();
+        breaker.failure(1, Duration::from_secs(0));
+        assert!(breaker.allow(Duration::from_secs(60)));
+        assert!(!breaker.allow(Duration::from_secs(60)));
+        breaker.success();
+        assert!(breaker.allow(Duration::from_secs(60)));
+    }
+
+    #[tokio::test]
+    async fn test_open_circuit_fails_fast() -> Result<(), Error> {
+        let outbound = outbound(policy(1, 2))?;
+        let res = outbound
+            .send(Upstream::Search, outbound.get("http://127.0.0.1:1/"))
+            .await;
+        assert!(matches!(res, Err(ApiError::ProxyError)));
+        let res = outbound
+            .send(Upstream::Search, outbound.get("http://127.0.0.1:1/"))
+            .await;
+        assert!(matches!(res, Err(ApiError::UpstreamUnavailable("search"))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_disabled_breaker_never_opens() -> Result<(), Error> {
+        let outbound = outbound(policy(0, 0))?;
+        for _ in 0..3 {
+            let res = outbound
+                .send(Upstream::Search, outbound.get("http://127.0.0.1:1/"))
+                .await;
+            assert!(matches!(res, Err(ApiError::ProxyError)));
+        }
+        Ok(())
+    }
+}
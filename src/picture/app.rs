@@ -0,0 +1,234 @@
+use crate::error::ApiError;
+use crate::metrics::Metrics;
+use crate::metrics::Upstream;
+use crate::orgchart::app::USERINFO_ENCODE_SET;
+use crate::outbound::Outbound;
+use crate::picture::error::PictureError;
+use crate::picture::sanitize::sanitize;
+use crate::proxy::proxy;
+use crate::settings::Fossil;
+use crate::settings::Picture;
+use actix_multipart::Multipart;
+use actix_web::dev::HttpServiceFactory;
+use actix_web::http::header::HeaderValue;
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::web;
+use actix_web::web::Data;
+use actix_web::web::Path;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use cis_client::error::CisClientError;
+use cis_client::error::ProfileError;
+use cis_client::getby::GetBy;
+use cis_client::AsyncCisClientTrait;
+use cis_profile::schema::Display;
+use cis_profile::schema::StandardAttributeString;
+use dino_park_gate::scope::ScopeAndUser;
+use dino_park_guard::guard;
+use futures::TryStreamExt;
+use log::error;
+use log::info;
+use percent_encoding::utf8_percent_encode;
+use reqwest::header::CONTENT_TYPE;
+use std::sync::Arc;
+
+const PICTURE_FIELD: &str = "picture";
+
+#[derive(Deserialize)]
+struct IntermediateResponse {
+    intermediate: String,
+}
+
+#[derive(Serialize)]
+struct UploadResponse {
+    picture: String,
+}
+
+async fn read_picture(payload: Multipart, max_bytes: usize) -> Result<Vec<u8>, PictureError> {
+    let mut payload = payload;
+    while let Some(mut field) = payload.try_next().await.map_err(|e| {
+        info!("invalid multipart upload: {}", e);
+        PictureError::Invalid
+    })? {
+        if field.name() != Some(PICTURE_FIELD) {
+            continue;
+        }
+        let mut bytes = vec![];
+        while let Some(chunk) = field.try_next().await.map_err(|e| {
+            info!("invalid multipart upload: {}", e);
+            PictureError::Invalid
+        })? {
+            if bytes.len() + chunk.len() > max_bytes {
+                return Err(PictureError::TooLarge(max_bytes));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        return Ok(bytes);
+    }
+    Err(PictureError::Missing)
+}
+
+#[guard(Authenticated)]
+async fn upload(
+    payload: Multipart,
+    outbound: Data<Arc<Outbound>>,
+    picture: Data<Picture>,
+    fossil: Data<Fossil>,
+) -> Result<HttpResponse, ApiError> {
+    let bytes = read_picture(payload, picture.max_bytes).await?;
+    let sanitized = sanitize(&bytes, &picture)?;
+    let res = outbound
+        .send(
+            Upstream::Fossil,
+            outbound
+                .post(format!("{}intermediate", fossil.upload_endpoint))
+                .header(CONTENT_TYPE, sanitized.content_type)
+                .body(sanitized.bytes),
+        )
+        .await?;
+    let IntermediateResponse { intermediate } = res
+        .error_for_status()
+        .map_err(|e| {
+            error!("fossil rejected picture: {}", e);
+            ApiError::ProxyError
+        })?
+        .json()
+        .await
+        .map_err(|e| {
+            error!("invalid fossil response: {}", e);
+            ApiError::ProxyError
+        })?;
+    Ok(HttpResponse::Ok().json(UploadResponse {
+        picture: format!("intermediate:{intermediate}"),
+    }))
+}
+
+/// Whether a picture may be shown to someone with the given `scope`. Owners
+/// always see their own picture.
+fn visible(picture: &StandardAttributeString, scope: &Display, owner: bool) -> bool {
+    let has_picture = picture.value.as_deref().is_some_and(|v| !v.is_empty());
+    let display_allowed = picture
+        .metadata
+        .display
+        .as_ref()
+        .is_some_and(|display| owner || display <= scope);
+    has_picture && display_allowed
+}
+
+#[guard(Public)]
+#[allow(clippy::too_many_arguments)]
+async fn retrieve<T: AsyncCisClientTrait>(
+    req: HttpRequest,
+    cis_client: Data<T>,
+    outbound: Data<Arc<Outbound>>,
+    metrics: Data<Metrics>,
+    picture: Data<Picture>,
+    fossil: Data<Fossil>,
+    scope_and_user: ScopeAndUser,
+    path: Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (uuid, size) = path.into_inner();
+    if !picture.sizes.contains(&size) {
+        return Err(ApiError::NotFound);
+    }
+    let scope: Display = scope_and_user.scope.clone().into();
+    // Fetched unfiltered since the owner isn't known yet, `visible` applies
+    // the requester's scope below.
+    let profile = metrics
+        .observe_upstream(
+            Upstream::Cis,
+            cis_client.get_user_by(&uuid, &GetBy::Uuid, Some(Display::Private.as_str())),
+        )
+        .await
+        .map_err(|e| match e {
+            CisClientError::ProfileError(ProfileError::ProfileDoesNotExist) => ApiError::NotFound,
+            e => {
+                error!("unable to retrieve profile for picture: {}", e);
+                ApiError::ProxyError
+            }
+        })?;
+    // Don't tell apart missing and hidden pictures, so uuids can't be probed.
+    let owner = profile.user_id.value.as_deref() == Some(scope_and_user.user_id.as_str());
+    if !visible(&profile.picture, &scope, owner) {
+        return Err(ApiError::NotFound);
+    }
+    let endpoint = format!(
+        "{}get/{}/{}",
+        fossil.upload_endpoint,
+        utf8_percent_encode(&uuid, USERINFO_ENCODE_SET),
+        size
+    );
+    let mut res = proxy(&outbound, Upstream::Fossil, &req, &endpoint).await?;
+    // Access depends on the requester, shared caches must not store these.
+    res.headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static("private"));
+    Ok(res)
+}
+
+pub fn picture_app<T: AsyncCisClientTrait + Send + Sync + 'static>(
+    cis_client: T,
+    picture: &Picture,
+    fossil: &Fossil,
+    outbound: &Arc<Outbound>,
+) -> impl HttpServiceFactory {
+    web::scope("/picture")
+        .app_data(Data::new(cis_client))
+        .app_data(Data::new(picture.clone()))
+        .app_data(Data::new(fossil.clone()))
+        .app_data(Data::new(Arc::clone(outbound)))
+        .service(web::resource("").route(web::post().to(upload)))
+        .service(web::resource("/{uuid}/{size}").route(web::get().to(retrieve::<T>)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn picture(value: &str, display: Option<Display>) -> StandardAttributeString {
+        let mut picture = StandardAttributeString::default();
+        picture.value = Some(String::from(value));
+        picture.metadata.display = display;
+        picture
+    }
+
+    #[test]
+    fn test_visible_within_scope() {
+        let p = picture("/avatar/uuid", Some(Display::Staff));
+        assert!(visible(&p, &Display::Staff, false));
+        let p = picture("/avatar/uuid", Some(Display::Public));
+        assert!(visible(&p, &Display::Authenticated, false));
+    }
+
+    #[test]
+    fn test_hidden_outside_scope() {
+        let p = picture("/avatar/uuid", Some(Display::Staff));
+        assert!(!visible(&p, &Display::Ndaed, false));
+        let p = picture("/avatar/uuid", Some(Display::Private));
+        assert!(!visible(&p, &Display::Staff, false));
+    }
+
+    #[test]
+    fn test_private_visible_to_owner() {
+        let p = picture("/avatar/uuid", Some(Display::Private));
+        assert!(visible(&p, &Display::Staff, true));
+        assert!(!visible(
+            &picture("", Some(Display::Private)),
+            &Display::Staff,
+            true
+        ));
+    }
+
+    #[test]
+    fn test_hidden_without_picture_or_display() {
+        assert!(!visible(
+            &picture("", Some(Display::Public)),
+            &Display::Staff,
+            false
+        ));
+        assert!(!visible(
+            &picture("/avatar/uuid", None),
+            &Display::Staff,
+            false
+        ));
+    }
+}
@@ -0,0 +1,21 @@
+// DEBT: see `crate::error`, deriving `Fail` triggers `non_local_definitions`.
+#![allow(non_local_definitions)]
+
+use failure::Fail;
+
+#[derive(Fail, Debug)]
+pub enum PictureError {
+    #[fail(display = "No picture in request.")]
+    Missing,
+    #[fail(display = "Picture exceeds {} bytes.", _0)]
+    TooLarge(usize),
+    #[fail(display = "Unsupported picture type, use PNG or JPEG.")]
+    UnsupportedType,
+    #[fail(
+        display = "Picture dimensions must be between {} and {} pixels.",
+        _0, _1
+    )]
+    InvalidDimensions(u32, u32),
+    #[fail(display = "Invalid picture.")]
+    Invalid,
+}
@@ -0,0 +1,141 @@
+use crate::picture::error::PictureError;
+use crate::settings::Picture;
+use image::DynamicImage;
+use image::ImageDecoder;
+use image::ImageFormat;
+use image::ImageReader;
+use log::info;
+use std::io::Cursor;
+
+pub struct Sanitized {
+    pub bytes: Vec<u8>,
+    pub content_type: &'static str,
+}
+
+fn invalid(e: impl std::fmt::Display) -> PictureError {
+    info!("invalid picture: {}", e);
+    PictureError::Invalid
+}
+
+/// Validates an uploaded picture and re-encodes it. Re-encoding drops all
+/// metadata (EXIF, comments, …) after applying the EXIF orientation.
+pub fn sanitize(bytes: &[u8], settings: &Picture) -> Result<Sanitized, PictureError> {
+    if bytes.len() > settings.max_bytes {
+        return Err(PictureError::TooLarge(settings.max_bytes));
+    }
+    let reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(invalid)?;
+    let format = match reader.format() {
+        Some(format @ ImageFormat::Png) | Some(format @ ImageFormat::Jpeg) => format,
+        _ => return Err(PictureError::UnsupportedType),
+    };
+    let mut decoder = reader.into_decoder().map_err(invalid)?;
+    // check the dimensions before decoding to not blow up on huge pictures
+    let (width, height) = decoder.dimensions();
+    let allowed = settings.min_dimension..=settings.max_dimension;
+    if !allowed.contains(&width) || !allowed.contains(&height) {
+        return Err(PictureError::InvalidDimensions(
+            settings.min_dimension,
+            settings.max_dimension,
+        ));
+    }
+    let orientation = decoder.orientation().map_err(invalid)?;
+    let mut picture = DynamicImage::from_decoder(decoder).map_err(invalid)?;
+    picture.apply_orientation(orientation);
+
+    let mut out = Cursor::new(vec![]);
+    let content_type = match format {
+        ImageFormat::Jpeg => {
+            DynamicImage::ImageRgb8(picture.to_rgb8())
+                .write_to(&mut out, ImageFormat::Jpeg)
+                .map_err(invalid)?;
+            "image/jpeg"
+        }
+        _ => {
+            picture
+                .write_to(&mut out, ImageFormat::Png)
+                .map_err(invalid)?;
+            "image/png"
+        }
+    };
+    Ok(Sanitized {
+        bytes: out.into_inner(),
+        content_type,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::RgbImage;
+
+    fn settings() -> Picture {
+        Picture {
+            max_bytes: 1024 * 1024,
+            min_dimension: 16,
+            max_dimension: 256,
+            sizes: vec![],
+        }
+    }
+
+    fn encode(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+        let mut out = Cursor::new(vec![]);
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut out, format)
+            .unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn test_png_is_accepted() {
+        let sanitized = sanitize(&encode(32, 64, ImageFormat::Png), &settings()).unwrap();
+        assert_eq!(sanitized.content_type, "image/png");
+        let decoded = image::load_from_memory(&sanitized.bytes).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (32, 64));
+    }
+
+    #[test]
+    fn test_exif_is_stripped() {
+        let mut jpeg = encode(32, 32, ImageFormat::Jpeg);
+        // APP1 segment with an empty little endian TIFF structure
+        let exif = b"\xFF\xE1\x00\x16Exif\x00\x00II*\x00\x08\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+        jpeg.splice(2..2, exif.iter().cloned());
+        assert!(jpeg.windows(4).any(|w| w == b"Exif"));
+        let sanitized = sanitize(&jpeg, &settings()).unwrap();
+        assert_eq!(sanitized.content_type, "image/jpeg");
+        assert!(!sanitized.bytes.windows(4).any(|w| w == b"Exif"));
+    }
+
+    #[test]
+    fn test_invalid_dimensions_are_rejected() {
+        assert!(matches!(
+            sanitize(&encode(8, 32, ImageFormat::Png), &settings()),
+            Err(PictureError::InvalidDimensions(16, 256))
+        ));
+        assert!(matches!(
+            sanitize(&encode(32, 512, ImageFormat::Png), &settings()),
+            Err(PictureError::InvalidDimensions(16, 256))
+        ));
+    }
+
+    #[test]
+    fn test_unsupported_and_oversized_are_rejected() {
+        assert!(matches!(
+            sanitize(b"GIF89a not really", &settings()),
+            Err(PictureError::UnsupportedType)
+        ));
+        assert!(matches!(
+            sanitize(b"plain text", &settings()),
+            Err(PictureError::UnsupportedType)
+        ));
+        let settings = Picture {
+            max_bytes: 10,
+            ..settings()
+        };
+        assert!(matches!(
+            sanitize(&encode(32, 32, ImageFormat::Png), &settings),
+            Err(PictureError::TooLarge(10))
+        ));
+    }
+}
@@ -1,27 +1,188 @@
 use crate::error::ApiError;
+use crate::metrics::Upstream;
+use crate::outbound::Outbound;
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::HttpRequest;
 use actix_web::HttpResponse;
+use futures::StreamExt;
 use log::error;
 use log::info;
-use reqwest::Client;
-use serde_json::Value;
+use reqwest::header;
+use reqwest::Method;
+use reqwest::RequestBuilder;
+use reqwest::Response;
+use std::io;
 
 const PAYLOAD_SIZE: u64 = 2 * 1024 * 1024;
 
-pub async fn proxy(client: &Client, endpoint: &str) -> Result<HttpResponse, ApiError> {
-    info!("proxying: {}", endpoint);
-    let res = client.get(endpoint).send().await.map_err(|e| {
-        error!("proxy error: {}", e);
-        ApiError::ProxyError
+/// Request headers passed on to the upstream.
+const REQUEST_HEADERS: &[header::HeaderName] = &[
+    header::ACCEPT,
+    header::IF_NONE_MATCH,
+    header::IF_MODIFIED_SINCE,
+];
+
+/// Response headers passed back to the client.
+const RESPONSE_HEADERS: &[header::HeaderName] = &[
+    header::CACHE_CONTROL,
+    header::CONTENT_TYPE,
+    header::ETAG,
+    header::LAST_MODIFIED,
+];
+
+fn check_status(status: u16) -> Result<(), ApiError> {
+    match status {
+        404 => Err(ApiError::NotFound),
+        401 | 403 => Err(ApiError::UpstreamAuthError(status)),
+        400..=499 => Err(ApiError::UpstreamClientError(status)),
+        500..=599 => Err(ApiError::ProxyError),
+        _ => Ok(()),
+    }
+}
+
+/// A fully buffered upstream response.
+pub struct Fetched {
+    pub body: Bytes,
+    pub content_type: Option<String>,
+    pub etag: Option<String>,
+    pub cache_control: Option<String>,
+}
+
+async fn send(
+    outbound: &Outbound,
+    upstream: Upstream,
+    upstream_req: RequestBuilder,
+    endpoint: &str,
+) -> Result<Response, ApiError> {
+    let res = outbound.send(upstream, upstream_req).await?;
+    check_status(res.status().as_u16()).inspect_err(|_| {
+        info!("upstream returned {} for {}", res.status(), endpoint);
     })?;
     if let Some(payload_size) = res.content_length() {
         if payload_size > PAYLOAD_SIZE {
             error!("proxy error: payload too large");
-            return Err(ApiError::ProxyError);
+            return Err(ApiError::PayloadTooLarge);
         }
     }
-    let json = res.json::<Value>().await.map_err(|e| {
+    Ok(res)
+}
+
+/// Fetches `endpoint` and buffers the response for callers that need the
+/// whole body, e.g. for caching.
+pub async fn fetch(
+    outbound: &Outbound,
+    upstream: Upstream,
+    endpoint: &str,
+) -> Result<Fetched, ApiError> {
+    info!("fetching: {}", endpoint);
+    let res = send(outbound, upstream, outbound.get(endpoint), endpoint).await?;
+    let header = |name: header::HeaderName| {
+        res.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from)
+    };
+    let content_type = header(header::CONTENT_TYPE);
+    let etag = header(header::ETAG);
+    let cache_control = header(header::CACHE_CONTROL);
+    let mut body = vec![];
+    let mut stream = res.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| {
+            error!("proxy error: {}", e);
+            ApiError::ProxyError
+        })?;
+        if (body.len() + chunk.len()) as u64 > PAYLOAD_SIZE {
+            error!("proxy error: payload too large");
+            return Err(ApiError::PayloadTooLarge);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(Fetched {
+        body: Bytes::from(body),
+        content_type,
+        etag,
+        cache_control,
+    })
+}
+
+pub async fn proxy(
+    outbound: &Outbound,
+    upstream: Upstream,
+    req: &HttpRequest,
+    endpoint: &str,
+) -> Result<HttpResponse, ApiError> {
+    proxy_with_body(outbound, upstream, req, endpoint, Bytes::new()).await
+}
+
+/// Forwards `req` with `body` to `endpoint` and streams the upstream
+/// response back. Bodies larger than `PAYLOAD_SIZE` are rejected upfront if
+/// the upstream announces its size, otherwise the stream is aborted once the
+/// limit is exceeded.
+pub async fn proxy_with_body(
+    outbound: &Outbound,
+    upstream: Upstream,
+    req: &HttpRequest,
+    endpoint: &str,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    info!("proxying: {} {}", req.method(), endpoint);
+    let method = Method::from_bytes(req.method().as_str().as_bytes()).map_err(|e| {
         error!("proxy error: {}", e);
         ApiError::ProxyError
     })?;
-    Ok(HttpResponse::Ok().json(json))
+    let mut upstream_req = outbound.request(method, endpoint);
+    for name in REQUEST_HEADERS {
+        if let Some(value) = req.headers().get(name.as_str()) {
+            upstream_req = upstream_req.header(name, value.as_bytes());
+        }
+    }
+    if !body.is_empty() {
+        upstream_req = upstream_req.body(body.to_vec());
+    }
+    let res = send(outbound, upstream, upstream_req, endpoint).await?;
+
+    let status = StatusCode::from_u16(res.status().as_u16()).map_err(|_| ApiError::ProxyError)?;
+    let mut response = HttpResponse::build(status);
+    for name in RESPONSE_HEADERS {
+        if let Some(value) = res.headers().get(name) {
+            response.insert_header((name.as_str(), value.as_bytes()));
+        }
+    }
+    let mut received: u64 = 0;
+    let body = res.bytes_stream().map(move |chunk| {
+        let chunk = chunk.map_err(|e| {
+            error!("proxy error: {}", e);
+            io::Error::other(e)
+        })?;
+        received += chunk.len() as u64;
+        if received > PAYLOAD_SIZE {
+            error!("proxy error: payload too large");
+            return Err(io::Error::other("payload too large"));
+        }
+        Ok(chunk)
+    });
+    Ok(response.streaming(body))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_status() {
+        assert!(check_status(200).is_ok());
+        assert!(check_status(304).is_ok());
+        assert!(matches!(check_status(404), Err(ApiError::NotFound)));
+        assert!(matches!(
+            check_status(403),
+            Err(ApiError::UpstreamAuthError(403))
+        ));
+        assert!(matches!(
+            check_status(409),
+            Err(ApiError::UpstreamClientError(409))
+        ));
+        assert!(matches!(check_status(503), Err(ApiError::ProxyError)));
+    }
 }
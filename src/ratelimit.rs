@@ -0,0 +1,365 @@
+use crate::error::ApiError;
+use crate::metrics::Metrics;
+use crate::settings::RateLimit;
+use crate::settings::RateLimits;
+use actix_web::dev::forward_ready;
+use actix_web::dev::Service;
+use actix_web::dev::ServiceRequest;
+use actix_web::dev::ServiceResponse;
+use actix_web::dev::Transform;
+use actix_web::http::header::X_FORWARDED_FOR;
+use actix_web::HttpMessage;
+use dino_park_gate::scope::ScopeAndUser;
+use dino_park_trust::Trust;
+use futures::future::ready;
+use futures::future::LocalBoxFuture;
+use futures::future::Ready;
+use ipnet::IpNet;
+use prometheus::IntCounterVec;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Number of tracked keys after which refilled buckets are dropped.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket rate limiter keyed by e.g. user id. Every key may use `burst`
+/// requests at once, tokens are refilled with `per_minute / 60` per second.
+pub struct RateLimiter {
+    burst: f64,
+    per_second: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(settings: &RateLimit) -> Self {
+        RateLimiter {
+            burst: f64::from(settings.burst),
+            per_second: f64::from(settings.per_minute) / 60.0,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token for `key`. If none is left the time until the next
+    /// token is available is returned.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > PRUNE_THRESHOLD {
+            let (burst, per_second) = (self.burst, self.per_second);
+            buckets.retain(|_, bucket| {
+                let elapsed = now.saturating_duration_since(bucket.updated);
+                bucket.tokens + elapsed.as_secs_f64() * per_second < burst
+            });
+        }
+        let bucket = buckets.entry(key.to_owned()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.per_second).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if self.per_second > 0.0 {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.per_second,
+            ))
+        } else {
+            Err(Duration::MAX)
+        }
+    }
+}
+
+pub const API_PREFIX: &str = "/api/v4";
+/// Label for requests not matching a configured route.
+const DEFAULT_ROUTE: &str = "default";
+
+/// First path segment below the API prefix, e.g. `search` for
+/// `/api/v4/search/simple/`.
+fn route(path: &str) -> &str {
+    path.strip_prefix(API_PREFIX)
+        .unwrap_or(path)
+        .trim_start_matches('/')
+        .split('/')
+        .next()
+        .unwrap_or_default()
+}
+
+/// The peer address, or if the peer is a trusted proxy the right-most
+/// `X-Forwarded-For` hop not added by a trusted proxy. Entries left of that
+/// are set by the client and can't be trusted.
+fn client_ip(req: &ServiceRequest, trusted_proxies: &[IpNet]) -> Option<IpAddr> {
+    let trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    let forwarded: Vec<&str> = req
+        .headers()
+        .get_all(X_FORWARDED_FOR)
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect();
+    let mut client = req.peer_addr()?.ip();
+    for hop in forwarded.iter().rev() {
+        if !trusted(&client) {
+            break;
+        }
+        match hop.trim().parse() {
+            Ok(ip) => client = ip,
+            Err(_) => break,
+        }
+    }
+    Some(client)
+}
+
+/// Authenticated requests are limited per user, public ones per client IP.
+fn client_key(req: &ServiceRequest, trusted_proxies: &[IpNet]) -> String {
+    let user_id = req
+        .extensions()
+        .get::<ScopeAndUser>()
+        .filter(|scope_and_user| scope_and_user.scope != Trust::Public)
+        .map(|scope_and_user| scope_and_user.user_id.clone());
+    match user_id {
+        Some(user_id) => format!("user:{user_id}"),
+        None => match client_ip(req, trusted_proxies) {
+            Some(ip) => format!("ip:{ip}"),
+            None => String::from("ip:unknown"),
+        },
+    }
+}
+
+struct Limiters {
+    routes: HashMap<String, RateLimiter>,
+    default: RateLimiter,
+    trusted_proxies: Vec<IpNet>,
+    rate_limited: IntCounterVec,
+}
+
+impl Limiters {
+    fn check(&self, req: &ServiceRequest) -> Result<(), Duration> {
+        let route = route(req.path());
+        let method_route = format!("{} {}", req.method(), route);
+        let (label, limiter) = match self
+            .routes
+            .get_key_value(&method_route)
+            .or_else(|| self.routes.get_key_value(route))
+        {
+            Some((route, limiter)) => (route.as_str(), limiter),
+            None => (DEFAULT_ROUTE, &self.default),
+        };
+        limiter
+            .check(&client_key(req, &self.trusted_proxies))
+            .inspect_err(|_| {
+                self.rate_limited.with_label_values(&[label]).inc();
+            })
+    }
+}
+
+/// Middleware applying the per route budgets from [`RateLimits`]. Must run
+/// after the scope middleware to see the user.
+#[derive(Clone)]
+pub struct RateLimiting {
+    limiters: Arc<Limiters>,
+}
+
+impl RateLimiting {
+    pub fn new(settings: &RateLimits, metrics: &Metrics) -> Self {
+        RateLimiting {
+            limiters: Arc::new(Limiters {
+                routes: settings
+                    .routes
+                    .iter()
+                    .map(|(route, limit)| (route.clone(), RateLimiter::new(limit)))
+                    .collect(),
+                default: RateLimiter::new(&settings.default),
+                trusted_proxies: settings.trusted_proxies.clone(),
+                rate_limited: metrics.counters.rate_limited.clone(),
+            }),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiting
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RateLimitingMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitingMiddleware {
+            service,
+            limiters: Arc::clone(&self.limiters),
+        }))
+    }
+}
+
+pub struct RateLimitingMiddleware<S> {
+    service: S,
+    limiters: Arc<Limiters>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if let Err(retry_after) = self.limiters.check(&req) {
+            let retry_after = retry_after.as_secs().saturating_add(1);
+            return Box::pin(ready(Err(ApiError::RateLimited(retry_after).into())));
+        }
+        Box::pin(self.service.call(req))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use failure::Error;
+
+    fn limiter(burst: u32, per_minute: u32) -> RateLimiter {
+        RateLimiter::new(&RateLimit { burst, per_minute })
+    }
+
+    #[test]
+    fn test_burst_then_limited() {
+        let limiter = limiter(2, 60);
+        let now = Instant::now();
+        assert!(limiter.check_at("a", now).is_ok());
+        assert!(limiter.check_at("a", now).is_ok());
+        let retry_after = limiter.check_at("a", now).unwrap_err();
+        assert_eq!(retry_after, Duration::from_secs(1));
+        assert!(limiter.check_at("b", now).is_ok());
+    }
+
+    #[test]
+    fn test_tokens_are_refilled() {
+        let limiter = limiter(1, 60);
+        let now = Instant::now();
+        assert!(limiter.check_at("a", now).is_ok());
+        assert!(limiter.check_at("a", now).is_err());
+        assert!(limiter
+            .check_at("a", now + Duration::from_millis(1500))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_route() {
+        assert_eq!(route("/api/v4/search/simple/"), "search");
+        assert_eq!(route("/api/v4/graphql"), "graphql");
+        assert_eq!(route("/api/v4"), "");
+    }
+
+    #[test]
+    fn test_public_requests_are_limited_per_ip() {
+        let req = TestRequest::default()
+            .peer_addr("10.0.0.1:4242".parse().unwrap())
+            .to_srv_request();
+        assert_eq!(client_key(&req, &[]), "ip:10.0.0.1");
+    }
+
+    #[test]
+    fn test_forwarded_for_only_from_trusted_proxies() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        let req = |peer: &str| {
+            TestRequest::default()
+                .peer_addr(peer.parse().unwrap())
+                .insert_header((X_FORWARDED_FOR, "1.1.1.1, 2.2.2.2, 10.0.0.2"))
+                .to_srv_request()
+        };
+        // spoofed by the client
+        assert_eq!(client_key(&req("3.3.3.3:4242"), &trusted), "ip:3.3.3.3");
+        // 1.1.1.1 was added by the client, 2.2.2.2 by our trusted proxies
+        assert_eq!(client_key(&req("10.0.0.1:4242"), &trusted), "ip:2.2.2.2");
+        assert_eq!(client_key(&req("10.0.0.1:4242"), &[]), "ip:10.0.0.1");
+    }
+
+    #[test]
+    fn test_routes_have_own_budgets() -> Result<(), Error> {
+        let metrics = Metrics::new()?;
+        let settings = RateLimits {
+            routes: vec![(
+                String::from("search"),
+                RateLimit {
+                    burst: 1,
+                    per_minute: 1,
+                },
+            )]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        let limiting = RateLimiting::new(&settings, &metrics);
+        let search = || TestRequest::with_uri("/api/v4/search/simple/").to_srv_request();
+        let graphql = || TestRequest::with_uri("/api/v4/graphql").to_srv_request();
+        assert!(limiting.limiters.check(&search()).is_ok());
+        assert!(limiting.limiters.check(&search()).is_err());
+        assert!(limiting.limiters.check(&graphql()).is_ok());
+        assert_eq!(
+            metrics
+                .counters
+                .rate_limited
+                .with_label_values(&["search"])
+                .get(),
+            1
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_routes_by_method() -> Result<(), Error> {
+        let metrics = Metrics::new()?;
+        let settings = RateLimits {
+            routes: vec![(
+                String::from("POST picture"),
+                RateLimit {
+                    burst: 1,
+                    per_minute: 1,
+                },
+            )]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        let limiting = RateLimiting::new(&settings, &metrics);
+        let upload = || TestRequest::post().uri("/api/v4/picture").to_srv_request();
+        let retrieve = || TestRequest::with_uri("/api/v4/picture/uuid/264").to_srv_request();
+        assert!(limiting.limiters.check(&upload()).is_ok());
+        assert!(limiting.limiters.check(&upload()).is_err());
+        assert!(limiting.limiters.check(&retrieve()).is_ok());
+        assert!(limiting.limiters.check(&retrieve()).is_ok());
+        assert_eq!(
+            metrics
+                .counters
+                .rate_limited
+                .with_label_values(&["POST picture"])
+                .get(),
+            1
+        );
+        Ok(())
+    }
+}
@@ -1,14 +1,17 @@
 use crate::error::ApiError;
+use crate::metrics::Upstream;
+use crate::outbound::Outbound;
 use crate::proxy::proxy;
 use crate::settings::Search;
 use actix_web::dev::HttpServiceFactory;
 use actix_web::web;
 use actix_web::web::Data;
 use actix_web::web::Query;
+use actix_web::HttpRequest;
 use actix_web::HttpResponse;
 use dino_park_gate::scope::ScopeAndUser;
 use dino_park_guard::guard;
-use reqwest::Client;
+use std::sync::Arc;
 use url::Url;
 
 #[derive(Deserialize)]
@@ -20,7 +23,8 @@ struct SearchQuery {
 
 #[guard(Authenticated)]
 async fn handle_simple(
-    client: Data<Client>,
+    req: HttpRequest,
+    outbound: Data<Arc<Outbound>>,
     search: Data<Search>,
     scope_and_user: ScopeAndUser,
     query: Query<SearchQuery>,
@@ -37,15 +41,12 @@ async fn handle_simple(
     if let Some(a) = &query.a {
         url.query_pairs_mut().append_pair("a", a);
     }
-    proxy(&client, url.as_str()).await
+    proxy(&outbound, Upstream::Search, &req, url.as_str()).await
 }
 
-pub fn search_app(settings: &Search) -> impl HttpServiceFactory {
-    // SAFETY: this panics.
-    // https://docs.rs/reqwest/latest/reqwest/struct.Client.html#panics
-    let client = Client::new();
+pub fn search_app(settings: &Search, outbound: &Arc<Outbound>) -> impl HttpServiceFactory {
     web::scope("/search")
-        .app_data(Data::new(client))
+        .app_data(Data::new(Arc::clone(outbound)))
         .app_data(Data::new(settings.clone()))
         .service(web::resource("/simple/").route(web::get().to(handle_simple)))
 }
@@ -0,0 +1,49 @@
+use crate::error::ApiError;
+use crate::orgchart::app::USERINFO_ENCODE_SET;
+use crate::usernames::history::UsernameHistory;
+use actix_web::dev::HttpServiceFactory;
+use actix_web::http::header::LOCATION;
+use actix_web::web;
+use actix_web::web::Data;
+use actix_web::web::Path;
+use actix_web::HttpResponse;
+use dino_park_guard::guard;
+use log::error;
+use percent_encoding::utf8_percent_encode;
+use std::sync::Arc;
+
+/// Current username for a previous one, `None` if `username` was never
+/// given up or its grace period is over.
+pub async fn resolve(
+    history: &dyn UsernameHistory,
+    username: &str,
+) -> Result<Option<String>, ApiError> {
+    history
+        .lookup(username)
+        .await
+        .map(|previous| previous.map(|p| p.current))
+        .map_err(|e| {
+            error!("unable to look up username history: {}", e);
+            ApiError::Unknown
+        })
+}
+
+#[guard(Authenticated)]
+async fn redirect(
+    history: Data<Arc<dyn UsernameHistory>>,
+    username: Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let current = resolve(&***history, &username)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    let location = format!("/p/{}", utf8_percent_encode(&current, USERINFO_ENCODE_SET));
+    Ok(HttpResponse::Found()
+        .insert_header((LOCATION, location))
+        .finish())
+}
+
+pub fn usernames_app(history: &Arc<dyn UsernameHistory>) -> impl HttpServiceFactory {
+    web::scope("/usernames")
+        .app_data(Data::new(Arc::clone(history)))
+        .service(web::resource("/{username}").route(web::get().to(redirect)))
+}
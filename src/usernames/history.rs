@@ -0,0 +1,234 @@
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+use deadpool_postgres::Pool;
+use failure::Error;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// A username which was given up by `user_id` in favor of `current`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PreviousUsername {
+    pub user_id: String,
+    pub current: String,
+    pub changed_at: DateTime<Utc>,
+}
+
+impl PreviousUsername {
+    /// Within the grace period only the previous owner may claim it again.
+    pub fn blocks(&self, user_id: &str, grace: Duration, now: DateTime<Utc>) -> bool {
+        self.user_id != user_id && now < self.changed_at + grace
+    }
+}
+
+pub trait UsernameHistory: Send + Sync {
+    /// Remembers that `user_id` changed their username from `old` to `new`.
+    fn record<'a>(
+        &'a self,
+        user_id: &'a str,
+        old: &'a str,
+        new: &'a str,
+    ) -> BoxFuture<'a, Result<(), Error>>;
+
+    /// The previous owner of `username`, `None` if it was never given up
+    /// or the grace period is over.
+    fn lookup<'a>(
+        &'a self,
+        username: &'a str,
+    ) -> BoxFuture<'a, Result<Option<PreviousUsername>, Error>>;
+}
+
+/// Shared by all replicas and kept across restarts.
+pub struct PostgresHistory {
+    pool: Pool,
+    grace: Duration,
+}
+
+impl PostgresHistory {
+    pub fn new(pool: Pool, grace: Duration) -> Self {
+        PostgresHistory { pool, grace }
+    }
+}
+
+impl UsernameHistory for PostgresHistory {
+    fn record<'a>(
+        &'a self,
+        user_id: &'a str,
+        old: &'a str,
+        new: &'a str,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let now = Utc::now();
+            let expired = now - self.grace;
+            let mut client = self.pool.get().await?;
+            let tx = client.transaction().await?;
+            // same rules as `MemoryHistory::record_at`
+            tx.execute(
+                "UPDATE username_history SET current = $1 WHERE user_id = $2",
+                &[&new, &user_id],
+            )
+            .await?;
+            tx.execute(
+                "DELETE FROM username_history WHERE username = $1 OR changed_at <= $2",
+                &[&new, &expired],
+            )
+            .await?;
+            tx.execute(
+                "INSERT INTO username_history (username, user_id, current, changed_at)
+                 VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (username) DO UPDATE SET
+                 user_id = EXCLUDED.user_id,
+                 current = EXCLUDED.current,
+                 changed_at = EXCLUDED.changed_at",
+                &[&old, &user_id, &new, &now],
+            )
+            .await?;
+            tx.commit().await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        username: &'a str,
+    ) -> BoxFuture<'a, Result<Option<PreviousUsername>, Error>> {
+        async move {
+            let expired = Utc::now() - self.grace;
+            let row = self
+                .pool
+                .get()
+                .await?
+                .query_opt(
+                    "SELECT user_id, current, changed_at FROM username_history
+                     WHERE username = $1 AND changed_at > $2",
+                    &[&username, &expired],
+                )
+                .await?;
+            Ok(row.map(|row| PreviousUsername {
+                user_id: row.get(0),
+                current: row.get(1),
+                changed_at: row.get(2),
+            }))
+        }
+        .boxed()
+    }
+}
+
+/// Only for local development without a database: the history is lost on
+/// restarts and not shared between replicas.
+pub struct MemoryHistory {
+    grace: Duration,
+    previous: Mutex<HashMap<String, PreviousUsername>>,
+}
+
+impl MemoryHistory {
+    pub fn new(grace: Duration) -> Self {
+        MemoryHistory {
+            grace,
+            previous: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn record_at(&self, user_id: &str, old: &str, new: &str, now: DateTime<Utc>) {
+        let mut previous = self.previous.lock().unwrap();
+        let grace = self.grace;
+        previous.retain(|_, entry| now < entry.changed_at + grace);
+        // older usernames of this user point to the new one directly
+        for entry in previous.values_mut().filter(|e| e.user_id == user_id) {
+            entry.current = String::from(new);
+        }
+        // a claimed username is not historical anymore
+        previous.remove(new);
+        previous.insert(
+            String::from(old),
+            PreviousUsername {
+                user_id: String::from(user_id),
+                current: String::from(new),
+                changed_at: now,
+            },
+        );
+    }
+}
+
+impl UsernameHistory for MemoryHistory {
+    fn record<'a>(
+        &'a self,
+        user_id: &'a str,
+        old: &'a str,
+        new: &'a str,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            self.record_at(user_id, old, new, Utc::now());
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        username: &'a str,
+    ) -> BoxFuture<'a, Result<Option<PreviousUsername>, Error>> {
+        async move {
+            let now = Utc::now();
+            Ok(self
+                .previous
+                .lock()
+                .unwrap()
+                .get(username)
+                .filter(|previous| now < previous.changed_at + self.grace)
+                .cloned())
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lookup_follows_renames() -> Result<(), Error> {
+        let history = MemoryHistory::new(Duration::days(30));
+        history.record("user1", "a", "b").await?;
+        history.record("user1", "b", "c").await?;
+        assert_eq!(history.lookup("a").await?.unwrap().current, "c");
+        assert_eq!(history.lookup("b").await?.unwrap().current, "c");
+        assert!(history.lookup("c").await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_claimed_username_is_not_historical() -> Result<(), Error> {
+        let history = MemoryHistory::new(Duration::days(30));
+        history.record("user1", "a", "b").await?;
+        history.record("user2", "x", "a").await?;
+        assert!(history.lookup("a").await?.is_none());
+        assert_eq!(history.lookup("x").await?.unwrap().current, "a");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_expired_usernames_are_forgotten() -> Result<(), Error> {
+        let history = MemoryHistory::new(Duration::days(30));
+        history.record_at("user1", "a", "b", Utc::now() - Duration::days(31));
+        assert!(history.lookup("a").await?.is_none());
+        history.record("user2", "x", "y").await?;
+        assert!(history.previous.lock().unwrap().get("a").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_blocks_others_within_grace() {
+        let history = MemoryHistory::new(Duration::days(30));
+        let then = Utc::now() - Duration::days(10);
+        history.record_at("user1", "a", "b", then);
+        let previous = history.previous.lock().unwrap().get("a").cloned().unwrap();
+        let now = Utc::now();
+        assert!(previous.blocks("user2", Duration::days(30), now));
+        assert!(!previous.blocks("user1", Duration::days(30), now));
+        assert!(!previous.blocks("user2", Duration::days(7), now));
+    }
+}